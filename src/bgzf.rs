@@ -0,0 +1,375 @@
+//! BGZF Compression and Decompression. Requires `bgzf` feature, enabled by default
+//!
+//! BGZF is the blocked GZIP format used by BAM files and tabix indexes. It's
+//! a GZIP file made of members holding up to 64K of data each, which tell
+//! their compressed size in a `BC` subfield of the extra field. A position in
+//! the data is then given by a 64 bit virtual offset: the offset of the block
+//! it's in within the file, shifted left by 16 bits, plus its offset within
+//! the block's data. The file ends with an empty block.
+//!
+//! # Example
+//!
+//! ```rust
+//! use compress::bgzf;
+//! use std::io::{Cursor, Read, Write};
+//!
+//! let mut e = bgzf::Encoder::new(Vec::new());
+//! e.write_all(b"first record\n").unwrap();
+//! let offset = e.virtual_offset();
+//! e.write_all(b"second record\n").unwrap();
+//! let (encoded, result) = e.finish();
+//! result.unwrap();
+//!
+//! let mut d = bgzf::Decoder::new(Cursor::new(encoded));
+//! d.seek_virtual(offset).unwrap();
+//! let mut rest = String::new();
+//! d.read_to_string(&mut rest).unwrap();
+//! assert_eq!(rest, "second record\n");
+//! ```
+//!
+//! # Related links
+//!
+//! * https://samtools.github.io/hts-specs/SAMv1.pdf - The SAM/BAM format
+//!   specification, which defines BGZF in section 4.1
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::flate;
+use crate::gzip::{self, Header, Subfield};
+
+/// Amount of data put in a block, leaving room for it to grow when it can't
+/// be compressed
+pub const BLOCK_SIZE: usize = 0xff00;
+
+// Largest size of a whole block
+const MAX_BLOCK: usize = 0x10000;
+
+// Sizes of the header of a block, with its BC subfield, and of the trailer
+const HEADER_SIZE: usize = 18;
+const TRAILER_SIZE: usize = 8;
+
+// The empty block ending a file
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+fn invalid<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// Structure used to compress data into a BGZF file. The data is cut into
+/// blocks of `BLOCK_SIZE`, unless the encoder is flushed earlier. The
+/// wrapped stream can be re-acquired through `finish()`.
+pub struct Encoder<W> {
+    w: W,
+    level: u8,
+    // data of the block being filled
+    buf: Vec<u8>,
+    // offset of that block in the file
+    coffset: u64,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new BGZF encoder which will have its output written to the
+    /// given output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, flate::DEFAULT_LEVEL)
+    }
+
+    /// Creates a new BGZF encoder with the given compression level, see
+    /// `flate::Encoder::with_level`
+    pub fn with_level(w: W, level: u8) -> Encoder<W> {
+        Encoder {
+            w,
+            level,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            coffset: 0,
+        }
+    }
+
+    /// Returns the virtual offset of the next byte to be written
+    pub fn virtual_offset(&self) -> u64 {
+        self.coffset << 16 | self.buf.len() as u64
+    }
+
+    // Compresses the data so far into a block of its own
+    fn write_block(&mut self) -> io::Result<()> {
+        let header = Header {
+            extra: vec![Subfield {
+                id: *b"BC",
+                data: vec![0, 0],
+            }],
+            ..Header::default()
+        };
+        let mut e = gzip::Encoder::with_header(Vec::new(), self.level, header);
+        e.write_all(&self.buf)?;
+        let (mut block, result) = e.finish();
+        result?;
+        debug_assert!(block.len() <= MAX_BLOCK);
+        // the size only gets known now
+        let bsize = (block.len() - 1) as u16;
+        block[16..HEADER_SIZE].copy_from_slice(&bsize.to_le_bytes());
+        self.w.write_all(&block)?;
+        self.coffset += block.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The last block is written along with the empty block ending the
+    /// file, and then the wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let mut result = Ok(());
+        if !self.buf.is_empty() {
+            result = self.write_block();
+        }
+        let result = result.and_then(|_| self.w.write_all(&EOF_BLOCK));
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let n = (BLOCK_SIZE - self.buf.len()).min(rest.len());
+            self.buf.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+            if self.buf.len() == BLOCK_SIZE {
+                self.write_block()?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Ends the block being filled, so that all the data so far is written
+    /// out.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.write_block()?;
+        }
+        self.w.flush()
+    }
+}
+
+/// Structure used to decode a BGZF file block by block, which can seek to
+/// virtual offsets when the underlying reader can seek. The wrapped stream
+/// can be re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    r: R,
+    block: Vec<u8>,
+    // data of the current block, read up to `pos`
+    data: Vec<u8>,
+    pos: usize,
+    // offsets of the current block and of the next one in the file
+    coffset: u64,
+    next: u64,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new BGZF decoder which will read the file from the current
+    /// position of the specified reader
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            block: Vec::with_capacity(MAX_BLOCK),
+            data: Vec::with_capacity(MAX_BLOCK),
+            pos: 0,
+            coffset: 0,
+            next: 0,
+        }
+    }
+
+    /// Returns the virtual offset of the next byte to be read
+    pub fn virtual_offset(&self) -> u64 {
+        self.coffset << 16 | self.pos as u64
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r
+    }
+
+    // Reads and decodes the next block, returning false at the end of the
+    // file
+    fn read_block(&mut self) -> io::Result<bool> {
+        self.block.clear();
+        self.data.clear();
+        self.pos = 0;
+        self.coffset = self.next;
+        let n = self.r.by_ref().take(12).read_to_end(&mut self.block)?;
+        if n == 0 {
+            return Ok(false);
+        }
+        if n < 12 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "BGZF block cut short",
+            ));
+        }
+        if self.block[..4] != [0x1f, 0x8b, 8, 4] {
+            return invalid("invalid BGZF block header");
+        }
+        let xlen = u16::from_le_bytes([self.block[10], self.block[11]]) as usize;
+        self.block.resize(12 + xlen, 0);
+        self.r.read_exact(&mut self.block[12..])?;
+        let bsize = match gzip::parse_extra(&self.block[12..])?
+            .iter()
+            .find(|s| s.id == *b"BC" && s.data.len() == 2)
+        {
+            Some(s) => u16::from_le_bytes([s.data[0], s.data[1]]) as usize + 1,
+            None => return invalid("BGZF block size missing"),
+        };
+        if bsize < self.block.len() + TRAILER_SIZE {
+            return invalid("invalid BGZF block size");
+        }
+        let start = self.block.len();
+        self.block.resize(bsize, 0);
+        self.r.read_exact(&mut self.block[start..])?;
+        self.next = self.coffset + bsize as u64;
+        gzip::Decoder::new(&self.block[..]).read_to_end(&mut self.data)?;
+        Ok(true)
+    }
+}
+
+impl<R: Read + Seek> Decoder<R> {
+    /// Moves on to the given virtual offset, as given by `virtual_offset()`
+    /// while writing or reading the file. The underlying reader must have
+    /// been at the start of the file when this decoder was created.
+    pub fn seek_virtual(&mut self, offset: u64) -> io::Result<()> {
+        let (coffset, uoffset) = (offset >> 16, (offset & 0xffff) as usize);
+        self.r.seek(SeekFrom::Start(coffset))?;
+        self.next = coffset;
+        // an offset right at the end of the file is fine
+        if !self.read_block()? && uoffset == 0 {
+            return Ok(());
+        }
+        if uoffset > self.data.len() {
+            return invalid("virtual offset past the end of its BGZF block");
+        }
+        self.pos = uoffset;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // empty blocks, like the one ending the file, are skipped over
+        while self.pos == self.data.len() {
+            if !self.read_block()? {
+                return Ok(0);
+            }
+        }
+        let n = (self.data.len() - self.pos).min(buf.len());
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decoder, Encoder, BLOCK_SIZE, EOF_BLOCK};
+    use gzip;
+    use std::io::{Cursor, Read, Write};
+
+    fn encode(data: &[u8], level: u8) -> Vec<u8> {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        for chunk in data.chunks(10000) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    // Offsets and sizes of the blocks of a file
+    fn blocks(data: &[u8]) -> Vec<(usize, usize)> {
+        let mut blocks = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let size = u16::from_le_bytes([data[pos + 16], data[pos + 17]]) as usize + 1;
+            blocks.push((pos, size));
+            pos += size;
+        }
+        blocks
+    }
+
+    #[test]
+    fn roundtrips() {
+        let data = &include_bytes!("data/test.large")[..300000];
+        for &level in [0, 1, 6].iter() {
+            let encoded = encode(data, level);
+            assert!(encoded.ends_with(&EOF_BLOCK));
+            let blocks = blocks(&encoded);
+            assert_eq!(blocks.len(), 300000 / BLOCK_SIZE + 2);
+            assert!(blocks.iter().all(|&(_, size)| size <= 0x10000));
+
+            let mut decoded = Vec::new();
+            Decoder::new(&encoded[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert!(decoded == data);
+            // it's a valid GZIP file all the same
+            decoded.clear();
+            gzip::Decoder::new(&encoded[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert!(decoded == data);
+        }
+        assert_eq!(encode(b"", 6), EOF_BLOCK);
+    }
+
+    #[test]
+    fn virtual_offsets() {
+        let text = &include_bytes!("data/test.txt")[..];
+        let mut e = Encoder::new(Vec::new());
+        let mut records = Vec::new();
+        for i in 0..200 {
+            let record = &text[i * 7 % 1000..][..(i * 131) % 2000];
+            records.push((e.virtual_offset(), record));
+            e.write_all(record).unwrap();
+            if i % 50 == 0 {
+                e.flush().unwrap();
+            }
+        }
+        let end = e.virtual_offset();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+
+        let mut d = Decoder::new(Cursor::new(&encoded[..]));
+        for &(offset, record) in records.iter().rev() {
+            d.seek_virtual(offset).unwrap();
+            assert_eq!(d.virtual_offset(), offset);
+            let mut buf = vec![0; record.len()];
+            d.read_exact(&mut buf).unwrap();
+            assert!(buf == record);
+        }
+
+        // reading along keeps track of the offset
+        d.seek_virtual(0).unwrap();
+        for &(_, record) in records.iter() {
+            let mut buf = vec![0; record.len()];
+            d.read_exact(&mut buf).unwrap();
+            assert!(buf == record);
+        }
+        d.seek_virtual(end).unwrap();
+        assert_eq!(d.read(&mut [0; 10]).unwrap(), 0);
+        assert!(d.seek_virtual(3 << 16).is_err());
+    }
+
+    #[test]
+    fn not_bgzf() {
+        let data = include_bytes!("data/test.txt.gz");
+        assert!(Decoder::new(&data[..])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+        let mut encoded = encode(b"hello", 6);
+        encoded[20] ^= 1;
+        assert!(Decoder::new(&encoded[..])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+}
@@ -3,7 +3,9 @@
 Adler-32 checksum
 
 This implementation is based off the example found at
-http://en.wikipedia.org/wiki/Adler-32.
+http://en.wikipedia.org/wiki/Adler-32, with the sums only taken modulo 65521
+every 5552 bytes as in zlib. The checksum of a window sliding along the data
+can be kept up to date a byte at a time, as rsync does.
 
 # Example
 
@@ -16,8 +18,15 @@ let checksum = state.result();
 
 */
 
+use std::hash::Hasher;
+
+use super::Checksum;
+
 const MOD_ADLER: u32 = 65521;
 
+// Most bytes which can be summed before the sums may overflow 32 bits
+const NMAX: usize = 5552;
+
 /// Adler state for 32 bits
 pub struct State32 {
     a: u32,
@@ -32,10 +41,41 @@ impl State32 {
 
     /// Mutate the state for given data
     pub fn feed(&mut self, buf: &[u8]) {
-        for byte in buf.iter() {
-            self.a = (self.a + *byte as u32) % MOD_ADLER;
-            self.b = (self.a + self.b) % MOD_ADLER;
+        let (mut a, mut b) = (self.a, self.b);
+        for chunk in buf.chunks(NMAX) {
+            let mut quads = chunk.chunks_exact(4);
+            for q in &mut quads {
+                a += q[0] as u32;
+                b += a;
+                a += q[1] as u32;
+                b += a;
+                a += q[2] as u32;
+                b += a;
+                a += q[3] as u32;
+                b += a;
+            }
+            for &byte in quads.remainder() {
+                a += byte as u32;
+                b += a;
+            }
+            a %= MOD_ADLER;
+            b %= MOD_ADLER;
         }
+        self.a = a;
+        self.b = b;
+    }
+
+    /// Slide the window of `window` bytes this is the state of by a byte,
+    /// taking out `oldest`, its first byte, and adding `newest` after its
+    /// last one.
+    pub fn roll(&mut self, oldest: u8, newest: u8, window: usize) {
+        let m = MOD_ADLER;
+        let (oldest, newest) = (oldest as u32, newest as u32);
+        self.a = (self.a + m - oldest + newest) % m;
+        // every sum loses the oldest byte, the first one goes and the new
+        // one comes in
+        let lost = (window as u64 % m as u64 * oldest as u64 % m as u64) as u32;
+        self.b = (self.b + self.a + 2 * m - 1 - lost) % m;
     }
 
     /// Get checksum
@@ -43,9 +83,135 @@ impl State32 {
         (self.b << 16) | self.a
     }
 
+    /// Create the state of data with the given checksum
+    pub fn from_checksum(checksum: u32) -> State32 {
+        State32 {
+            a: checksum & 0xffff,
+            b: checksum >> 16,
+        }
+    }
+
     /// Reset the state
     pub fn reset(&mut self) {
         self.a = 1;
         self.b = 0;
     }
+
+    /// Combine the states of two pieces of data, `b` being the state of
+    /// `len_b` bytes following the data of `a`, into the state of the two
+    /// pieces one after the other. This lets pieces be checksummed separately.
+    pub fn combine(a: &State32, b: &State32, len_b: usize) -> State32 {
+        let m = MOD_ADLER as u64;
+        let rem = len_b as u64 % m;
+        // every byte of `b` adds the sum of `a`'s bytes, less the initial 1
+        let sum = (a.a as u64 + b.a as u64 + m - 1) % m;
+        let sums = (a.b as u64 + b.b as u64 + rem * (a.a as u64 + m - 1)) % m;
+        State32 {
+            a: sum as u32,
+            b: sums as u32,
+        }
+    }
+}
+
+impl Default for State32 {
+    fn default() -> State32 {
+        State32::new()
+    }
+}
+
+impl Checksum for State32 {
+    fn update(&mut self, buf: &[u8]) {
+        self.feed(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.result() as u64
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+
+    fn width(&self) -> u32 {
+        32
+    }
+}
+
+impl Hasher for State32 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.feed(bytes)
+    }
+
+    fn finish(&self) -> u64 {
+        self.result() as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{State32, MOD_ADLER};
+
+    fn adler(data: &[u8]) -> State32 {
+        let mut state = State32::new();
+        state.feed(data);
+        state
+    }
+
+    #[test]
+    fn known() {
+        assert_eq!(adler(b"").result(), 1);
+        assert_eq!(adler(b"Wikipedia").result(), 0x11e60398);
+    }
+
+    // The checksum with the modulo taken at every byte
+    fn slow(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1, 0);
+        for &byte in data.iter() {
+            a = (a + byte as u32) % MOD_ADLER;
+            b = (b + a) % MOD_ADLER;
+        }
+        b << 16 | a
+    }
+
+    #[test]
+    fn deferred_modulo() {
+        // bytes as large as they get, for the sums to be as large as well
+        let ones = vec![0xff; 20000];
+        let data = &include_bytes!("../data/test.large")[..20000];
+        for &len in [0, 3, 5551, 5552, 5553, 11104, 20000].iter() {
+            assert_eq!(adler(&ones[..len]).result(), slow(&ones[..len]));
+            assert_eq!(adler(&data[..len]).result(), slow(&data[..len]));
+        }
+        let mut state = State32::new();
+        for piece in ones.chunks(1001) {
+            state.feed(piece);
+        }
+        assert_eq!(state.result(), slow(&ones));
+    }
+
+    #[test]
+    fn rolling() {
+        let mut data = include_bytes!("../data/test.large")[..20000].to_vec();
+        data.extend(vec![0xff; 2000]);
+        for &window in [1, 16, 5552, 10000].iter() {
+            let mut state = adler(&data[..window]);
+            for i in window..data.len() {
+                state.roll(data[i - window], data[i], window);
+                if i % 97 == 0 || i == data.len() - 1 {
+                    let expected = adler(&data[i + 1 - window..i + 1]);
+                    assert_eq!(state.result(), expected.result());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn combine() {
+        let data = &include_bytes!("../data/test.large")[..100000];
+        for &at in [0, 1, 5552, 65521, 70000, data.len()].iter() {
+            let (x, y) = data.split_at(at);
+            let state = State32::combine(&adler(x), &adler(y), y.len());
+            assert_eq!(state.result(), adler(data).result());
+        }
+    }
 }
@@ -0,0 +1,298 @@
+/*!
+
+CRC checksums of any kind
+
+A CRC is defined by its width, its polynomial, whether its bits are
+reflected, the value it starts from and the value its result is XORed with,
+as in Ross Williams' parameterised model. The table for a given set of
+parameters is built when creating a `Crc`, which can be done at compile time.
+Presets are given for the usual variants; the plain CRC-32 is faster through
+the `crc32` module.
+
+# Example
+
+```rust
+use compress::checksum::crc::{self, Crc, State};
+static CASTAGNOLI: Crc = Crc::new(crc::CRC_32C);
+let mut state = State::new(&CASTAGNOLI);
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+*/
+
+use std::hash::Hasher;
+
+use super::Checksum;
+
+/// Parameters of a CRC
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Params {
+    /// Width in bits, from 8 to 64
+    pub width: u8,
+    /// The polynomial, without its top bit, and not reflected
+    pub poly: u64,
+    /// The value the CRC starts from, not reflected
+    pub init: u64,
+    /// Whether the bits of each byte are taken least significant first,
+    /// which makes for a reflected result as well
+    pub reflect: bool,
+    /// The value XORed to the CRC for the result
+    pub xorout: u64,
+}
+
+/// CRC-32 of IEEE 802.3, as used by gzip, zip and PNG
+pub const CRC_32: Params = Params {
+    width: 32,
+    poly: 0x04c11db7,
+    init: 0xffffffff,
+    reflect: true,
+    xorout: 0xffffffff,
+};
+
+/// CRC-32C (Castagnoli), as used by iSCSI, SCTP, ext4 and btrfs
+pub const CRC_32C: Params = Params {
+    width: 32,
+    poly: 0x1edc6f41,
+    init: 0xffffffff,
+    reflect: true,
+    xorout: 0xffffffff,
+};
+
+/// CRC-32 of bzip2, which isn't reflected
+pub const CRC_32_BZIP2: Params = Params {
+    width: 32,
+    poly: 0x04c11db7,
+    init: 0xffffffff,
+    reflect: false,
+    xorout: 0xffffffff,
+};
+
+/// CRC-64 of xz, with the polynomial of ECMA-182
+pub const CRC_64_XZ: Params = Params {
+    width: 64,
+    poly: 0x42f0e1eba9ea3693,
+    init: 0xffffffffffffffff,
+    reflect: true,
+    xorout: 0xffffffffffffffff,
+};
+
+/// CRC-64 as defined by ECMA-182
+pub const CRC_64_ECMA_182: Params = Params {
+    width: 64,
+    poly: 0x42f0e1eba9ea3693,
+    init: 0,
+    reflect: false,
+    xorout: 0,
+};
+
+/// CRC-16 of ARC and LHA
+pub const CRC_16_ARC: Params = Params {
+    width: 16,
+    poly: 0x8005,
+    init: 0,
+    reflect: true,
+    xorout: 0,
+};
+
+const fn mask(width: u8) -> u64 {
+    if width == 64 {
+        !0
+    } else {
+        (1 << width) - 1
+    }
+}
+
+// Reverses the order of the low `width` bits of `value`
+const fn reflect(value: u64, width: u8) -> u64 {
+    value.reverse_bits() >> (64 - width)
+}
+
+/// A CRC algorithm along with its table
+pub struct Crc {
+    params: Params,
+    table: [u64; 256],
+}
+
+impl Crc {
+    /// Create the algorithm with the given parameters
+    ///
+    /// # Panics
+    ///
+    /// If the width isn't from 8 to 64.
+    pub const fn new(params: Params) -> Crc {
+        let width = params.width;
+        assert!(width >= 8 && width <= 64, "invalid CRC width");
+        let mut table = [0; 256];
+        let mut n = 0;
+        while n < 256 {
+            let mut crc;
+            let mut k = 0;
+            if params.reflect {
+                let poly = reflect(params.poly, width);
+                crc = n as u64;
+                while k < 8 {
+                    crc = if crc & 1 != 0 {
+                        (crc >> 1) ^ poly
+                    } else {
+                        crc >> 1
+                    };
+                    k += 1;
+                }
+            } else {
+                let top = 1 << (width - 1);
+                crc = (n as u64) << (width - 8);
+                while k < 8 {
+                    crc = if crc & top != 0 {
+                        (crc << 1) ^ params.poly
+                    } else {
+                        crc << 1
+                    };
+                    k += 1;
+                }
+                crc &= mask(width);
+            }
+            table[n] = crc;
+            n += 1;
+        }
+        Crc { params, table }
+    }
+
+    /// Get the parameters of the algorithm
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Get the CRC of the given data at once
+    pub fn checksum(&self, buf: &[u8]) -> u64 {
+        let mut state = State::new(self);
+        state.feed(buf);
+        state.result()
+    }
+
+    fn init(&self) -> u64 {
+        let p = &self.params;
+        if p.reflect {
+            reflect(p.init, p.width)
+        } else {
+            p.init & mask(p.width)
+        }
+    }
+}
+
+/// CRC state, for any algorithm
+pub struct State<'a> {
+    crc: &'a Crc,
+    value: u64,
+}
+
+impl<'a> State<'a> {
+    /// Create a new state for the given algorithm
+    pub fn new(crc: &'a Crc) -> State<'a> {
+        State {
+            crc,
+            value: crc.init(),
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        let table = &self.crc.table;
+        let mut value = self.value;
+        if self.crc.params.reflect {
+            for &byte in buf.iter() {
+                value = table[((value ^ byte as u64) & 0xff) as usize] ^ (value >> 8);
+            }
+        } else {
+            let width = self.crc.params.width;
+            for &byte in buf.iter() {
+                let top = (value >> (width - 8)) ^ byte as u64;
+                value = table[(top & 0xff) as usize] ^ (value << 8);
+            }
+            value &= mask(width);
+        }
+        self.value = value;
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u64 {
+        let p = &self.crc.params;
+        (self.value ^ p.xorout) & mask(p.width)
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.value = self.crc.init();
+    }
+}
+
+impl<'a> Checksum for State<'a> {
+    fn update(&mut self, buf: &[u8]) {
+        self.feed(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.result()
+    }
+
+    fn reset(&mut self) {
+        State::reset(self)
+    }
+
+    fn width(&self) -> u32 {
+        self.crc.params.width as u32
+    }
+}
+
+impl<'a> Hasher for State<'a> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.feed(bytes)
+    }
+
+    fn finish(&self) -> u64 {
+        self.result()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        mask, Crc, Params, State, CRC_16_ARC, CRC_32, CRC_32C, CRC_32_BZIP2, CRC_64_ECMA_182,
+        CRC_64_XZ,
+    };
+    use checksum::crc32;
+
+    #[test]
+    fn known() {
+        // the check values of Greg Cook's catalogue of CRCs
+        let presets: [(Params, u64); 6] = [
+            (CRC_32, 0xcbf43926),
+            (CRC_32C, 0xe3069283),
+            (CRC_32_BZIP2, 0xfc891918),
+            (CRC_64_XZ, 0x995dc9bbdf1939fa),
+            (CRC_64_ECMA_182, 0x6c40df5f0b497347),
+            (CRC_16_ARC, 0xbb3d),
+        ];
+        for &(params, check) in presets.iter() {
+            let crc = Crc::new(params);
+            assert_eq!(crc.checksum(b"123456789"), check);
+            let empty = (params.init ^ params.xorout) & mask(params.width);
+            assert_eq!(crc.checksum(b""), empty);
+        }
+    }
+
+    #[test]
+    fn pieces() {
+        static CRC: Crc = Crc::new(CRC_32);
+        let data = &include_bytes!("../data/test.txt")[..];
+        let mut state = State::new(&CRC);
+        for piece in data.chunks(37) {
+            state.feed(piece);
+        }
+        let mut expected = crc32::State32::new();
+        expected.feed(data);
+        assert_eq!(state.result(), expected.result() as u64);
+        state.reset();
+        assert_eq!(state.result(), 0);
+    }
+}
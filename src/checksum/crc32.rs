@@ -0,0 +1,280 @@
+/*!
+
+CRC-32 checksum
+
+This is the CRC-32 of IEEE 802.3, as used by gzip, zip and PNG among others.
+It's computed 16 bytes at a time through as many tables (slicing-by-16),
+after Intel's slicing-by-8 and zlib's combination of CRCs.
+
+# Example
+
+```rust
+use compress::checksum::crc32;
+let mut state = crc32::State32::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+```
+
+*/
+
+use std::hash::Hasher;
+
+use super::Checksum;
+
+// The polynomial, bit reversed as the CRC is computed LSB first
+const POLY: u32 = 0xedb88320;
+
+// Number of bytes handled at once
+const SLICES: usize = 16;
+
+// The CRC of each byte value followed by `k` zero bytes, in table `k`
+static TABLES: [[u32; 256]; SLICES] = {
+    let mut tables = [[0; 256]; SLICES];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            crc = if crc & 1 != 0 {
+                POLY ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            k += 1;
+        }
+        tables[0][n] = crc;
+        n += 1;
+    }
+    let mut k = 1;
+    while k < SLICES {
+        let mut n = 0;
+        while n < 256 {
+            let crc = tables[k - 1][n];
+            tables[k][n] = (crc >> 8) ^ tables[0][(crc & 0xff) as usize];
+            n += 1;
+        }
+        k += 1;
+    }
+    tables
+};
+
+// Product of two polynomials modulo POLY, with the bits reversed like the
+// CRC's. `a` mustn't be zero.
+const fn multmodp(a: u32, mut b: u32) -> u32 {
+    let mut m = 1 << 31;
+    let mut p = 0;
+    loop {
+        if a & m != 0 {
+            p ^= b;
+            if a & (m - 1) == 0 {
+                return p;
+            }
+        }
+        m >>= 1;
+        b = if b & 1 != 0 { (b >> 1) ^ POLY } else { b >> 1 };
+    }
+}
+
+// x^(2^k) modulo POLY in entry `k`
+static X2N_TABLE: [u32; 32] = {
+    let mut table = [0; 32];
+    // x^1
+    let mut p = 1 << 30;
+    let mut k = 0;
+    while k < 32 {
+        table[k] = p;
+        p = multmodp(p, p);
+        k += 1;
+    }
+    table
+};
+
+// x^(8 * len) modulo POLY: the operator which appends `len` zero bytes
+fn zeros(len: u64) -> u32 {
+    // x^0
+    let mut p = 1 << 31;
+    let mut n = len;
+    let mut k = 3;
+    while n != 0 {
+        if n & 1 != 0 {
+            p = multmodp(X2N_TABLE[k & 31], p);
+        }
+        n >>= 1;
+        k += 1;
+    }
+    p
+}
+
+/// Combine the CRC-32s of two pieces of data, `crc2` being that of `len2`
+/// bytes following the data of `crc1`, into the CRC-32 of the two pieces
+/// one after the other. This lets pieces be checksummed separately, in
+/// parallel for instance.
+pub fn combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    multmodp(zeros(len2), crc1) ^ crc2
+}
+
+/// CRC-32 state
+pub struct State32 {
+    crc: u32,
+}
+
+impl State32 {
+    /// Create a new state
+    pub fn new() -> State32 {
+        State32 { crc: !0 }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, buf: &[u8]) {
+        let t = &TABLES;
+        let mut crc = self.crc;
+        let mut chunks = buf.chunks_exact(SLICES);
+        for c in &mut chunks {
+            let a = crc ^ u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+            crc = t[15][(a & 0xff) as usize]
+                ^ t[14][(a >> 8 & 0xff) as usize]
+                ^ t[13][(a >> 16 & 0xff) as usize]
+                ^ t[12][(a >> 24) as usize];
+            for (k, &byte) in c[4..].iter().enumerate() {
+                crc ^= t[11 - k][byte as usize];
+            }
+        }
+        for &byte in chunks.remainder() {
+            crc = t[0][((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.crc = crc;
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Create the state of data with the given checksum
+    pub fn from_checksum(checksum: u32) -> State32 {
+        State32 { crc: !checksum }
+    }
+
+    /// Reset the state
+    pub fn reset(&mut self) {
+        self.crc = !0;
+    }
+
+    /// Combine the states of two pieces of data, `b` being the state of
+    /// `len_b` bytes following the data of `a`, into the state of the two
+    /// pieces one after the other. See `combine()`.
+    pub fn combine(a: &State32, b: &State32, len_b: usize) -> State32 {
+        State32::from_checksum(combine(a.result(), b.result(), len_b as u64))
+    }
+}
+
+impl Default for State32 {
+    fn default() -> State32 {
+        State32::new()
+    }
+}
+
+impl Checksum for State32 {
+    fn update(&mut self, buf: &[u8]) {
+        self.feed(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.result() as u64
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+
+    fn width(&self) -> u32 {
+        32
+    }
+}
+
+impl Hasher for State32 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.feed(bytes)
+    }
+
+    fn finish(&self) -> u64 {
+        self.result() as u64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{combine, State32, POLY};
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut state = State32::new();
+        state.feed(data);
+        state.result()
+    }
+
+    #[test]
+    fn known() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414fa339
+        );
+    }
+
+    // The CRC computed a bit at a time
+    fn slow(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data.iter() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    POLY ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn slicing() {
+        let data = &include_bytes!("../data/test.txt")[..];
+        for start in 0..20 {
+            for len in (0..100).chain(900..980) {
+                let piece = &data[start..start + len];
+                assert_eq!(crc32(piece), slow(piece));
+            }
+        }
+        // feeding in pieces which don't line up
+        let mut state = State32::new();
+        for piece in data.chunks(37) {
+            state.feed(piece);
+        }
+        assert_eq!(state.result(), slow(data));
+    }
+
+    #[test]
+    fn combines() {
+        let data = &include_bytes!("../data/test.large")[..100000];
+        for &at in [0, 1, 15, 16, 4096, 70000, data.len()].iter() {
+            let (x, y) = data.split_at(at);
+            assert_eq!(combine(crc32(x), crc32(y), y.len() as u64), crc32(data));
+            let (mut a, mut b) = (State32::new(), State32::new());
+            a.feed(x);
+            b.feed(y);
+            assert_eq!(State32::combine(&a, &b, y.len()).result(), crc32(data));
+        }
+        assert_eq!(combine(0x12345678, 0, 0), 0x12345678);
+        // a length well past the data
+        let zeros = vec![0; 1 << 16];
+        let (mut all, mut tail) = (State32::new(), State32::new());
+        all.feed(b"abc");
+        for _ in 0..16 {
+            all.feed(&zeros);
+            tail.feed(&zeros);
+        }
+        assert_eq!(combine(crc32(b"abc"), tail.result(), 1 << 20), all.result());
+    }
+}
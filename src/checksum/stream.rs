@@ -0,0 +1,243 @@
+//! What all checksums have in common, and checksumming data as it's read or
+//! written
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::consume_with;
+
+/// A checksum of data fed to it piece by piece, which can be of any width up
+/// to 64 bits
+pub trait Checksum {
+    /// Mutate the state for given data
+    fn update(&mut self, buf: &[u8]);
+
+    /// Get checksum, in the low `width()` bits
+    fn finalize(&self) -> u64;
+
+    /// Reset the state to that of no data
+    fn reset(&mut self);
+
+    /// Get the width of the checksum in bits
+    fn width(&self) -> u32;
+}
+
+impl<C: Checksum + ?Sized> Checksum for &mut C {
+    fn update(&mut self, buf: &[u8]) {
+        (**self).update(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        (**self).finalize()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn width(&self) -> u32 {
+        (**self).width()
+    }
+}
+
+impl<C: Checksum + ?Sized> Checksum for Box<C> {
+    fn update(&mut self, buf: &[u8]) {
+        (**self).update(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        (**self).finalize()
+    }
+
+    fn reset(&mut self) {
+        (**self).reset()
+    }
+
+    fn width(&self) -> u32 {
+        (**self).width()
+    }
+}
+
+/// A reader which checksums the data read through it
+pub struct ChecksumReader<R, C> {
+    inner: R,
+    checksum: C,
+}
+
+impl<R: Read, C: Checksum> ChecksumReader<R, C> {
+    /// Creates a new reader which reads from `inner`, feeding `checksum` with
+    /// what it reads
+    pub fn new(inner: R, checksum: C) -> ChecksumReader<R, C> {
+        ChecksumReader { inner, checksum }
+    }
+
+    /// Returns the checksum of the data read so far
+    pub fn checksum(&self) -> &C {
+        &self.checksum
+    }
+
+    /// Returns a mutable reference to the checksum, to reset it for instance
+    pub fn checksum_mut(&mut self) -> &mut C {
+        &mut self.checksum
+    }
+
+    /// Returns a reference to the underlying reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Destroys this reader, returning the underlying reader and the
+    /// checksum
+    pub fn into_inner(self) -> (R, C) {
+        (self.inner, self.checksum)
+    }
+}
+
+impl<R: Read, C: Checksum> Read for ChecksumReader<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.checksum.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead, C: Checksum> BufRead for ChecksumReader<R, C> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let checksum = &mut self.checksum;
+        consume_with(&mut self.inner, amt, |buf| checksum.update(buf));
+    }
+}
+
+/// A writer which checksums the data written through it
+pub struct ChecksumWriter<W, C> {
+    inner: W,
+    checksum: C,
+}
+
+impl<W: Write, C: Checksum> ChecksumWriter<W, C> {
+    /// Creates a new writer which writes to `inner`, feeding `checksum` with
+    /// what it writes
+    pub fn new(inner: W, checksum: C) -> ChecksumWriter<W, C> {
+        ChecksumWriter { inner, checksum }
+    }
+
+    /// Returns the checksum of the data written so far
+    pub fn checksum(&self) -> &C {
+        &self.checksum
+    }
+
+    /// Returns a mutable reference to the checksum, to reset it for instance
+    pub fn checksum_mut(&mut self) -> &mut C {
+        &mut self.checksum
+    }
+
+    /// Returns a reference to the underlying writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Destroys this writer, returning the underlying writer and the
+    /// checksum
+    pub fn into_inner(self) -> (W, C) {
+        (self.inner, self.checksum)
+    }
+}
+
+impl<W: Write, C: Checksum> Write for ChecksumWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.checksum.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Checksum, ChecksumReader, ChecksumWriter};
+    use checksum::crc::{self, Crc};
+    use checksum::{adler, crc32, xxhash};
+    use std::collections::HashMap;
+    use std::hash::BuildHasherDefault;
+    use std::io::{self, BufRead, Read};
+
+    static CRC_64: Crc = Crc::new(crc::CRC_64_XZ);
+
+    fn checksums() -> Vec<Box<dyn Checksum>> {
+        vec![
+            Box::new(adler::State32::new()),
+            Box::new(crc32::State32::new()),
+            Box::new(crc::State::new(&CRC_64)),
+            Box::new(xxhash::State32::new()),
+            Box::new(xxhash::State64::with_seed(1)),
+        ]
+    }
+
+    #[test]
+    fn trait_objects() {
+        let expected = [
+            (0x091e01de, 32),
+            (0xcbf43926, 32),
+            (0x995dc9bbdf1939fa, 64),
+            (0x937bad67, 32),
+            (xxhash::xxh64(b"123456789", 1), 64),
+        ];
+        for (mut c, &(value, width)) in checksums().into_iter().zip(expected.iter()) {
+            c.update(b"12345");
+            c.update(b"6789");
+            assert_eq!((c.finalize(), c.width()), (value, width));
+            c.reset();
+            c.update(b"123456789");
+            assert_eq!(c.finalize(), value);
+        }
+    }
+
+    #[test]
+    fn adapters() {
+        let data = &include_bytes!("../data/test.txt")[..];
+        let mut crc = crc32::State32::new();
+        crc.feed(data);
+        let data_crc = crc.result();
+        for mut c in checksums() {
+            c.update(data);
+            let expected = c.finalize();
+            c.reset();
+
+            let mut r = ChecksumReader::new(data, c);
+            let mut w = ChecksumWriter::new(Vec::new(), crc32::State32::new());
+            io::copy(&mut r, &mut w).unwrap();
+            assert_eq!(r.checksum().finalize(), expected);
+            let (written, crc) = w.into_inner();
+            assert!(written == data);
+            assert_eq!(crc.result(), data_crc);
+        }
+
+        // only what's consumed counts
+        let mut r = ChecksumReader::new(data, adler::State32::new());
+        let mut line = String::new();
+        r.read_line(&mut line).unwrap();
+        let mut expected = adler::State32::new();
+        expected.feed(line.as_bytes());
+        assert_eq!(r.checksum().result(), expected.result());
+        r.read_to_end(&mut Vec::new()).unwrap();
+        expected.feed(&data[line.len()..]);
+        assert_eq!(r.checksum().result(), expected.result());
+    }
+
+    #[test]
+    fn hasher() {
+        let mut map: HashMap<&str, u32, BuildHasherDefault<xxhash::State64>> = HashMap::default();
+        map.insert("one", 1);
+        map.insert("two", 2);
+        assert_eq!(map.get("two"), Some(&2));
+        let mut map: HashMap<u64, u32, BuildHasherDefault<crc32::State32>> = HashMap::default();
+        map.insert(1, 1);
+        assert_eq!(map.get(&1), Some(&1));
+    }
+}
@@ -0,0 +1,405 @@
+/*!
+
+xxHash, 32 and 64 bit versions
+
+xxHash is a fast non-cryptographic hash by Yann Collet, which LZ4 frames
+use as their checksum. Both versions take a seed, which is 0 unless told
+otherwise.
+
+# Example
+
+```rust
+use compress::checksum::xxhash;
+let mut state = xxhash::State32::new();
+state.feed(b"abracadabra");
+let checksum = state.result();
+assert_eq!(checksum, xxhash::xxh32(b"abracadabra", 0));
+```
+
+# Related links
+
+* https://github.com/Cyan4973/xxHash/blob/dev/doc/xxhash_spec.md - The
+  specification of both versions
+
+*/
+
+use std::hash::Hasher;
+
+use super::Checksum;
+
+const P32_1: u32 = 2654435761;
+const P32_2: u32 = 2246822519;
+const P32_3: u32 = 3266489917;
+const P32_4: u32 = 668265263;
+const P32_5: u32 = 374761393;
+
+const P64_1: u64 = 0x9e3779b185ebca87;
+const P64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const P64_3: u64 = 0x165667b19e3779f9;
+const P64_4: u64 = 0x85ebca77c2b2ae63;
+const P64_5: u64 = 0x27d4eb2f165667c5;
+
+fn read32(buf: &[u8]) -> u32 {
+    u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]])
+}
+
+fn read64(buf: &[u8]) -> u64 {
+    read32(buf) as u64 | (read32(&buf[4..]) as u64) << 32
+}
+
+fn round32(acc: u32, lane: u32) -> u32 {
+    acc.wrapping_add(lane.wrapping_mul(P32_2))
+        .rotate_left(13)
+        .wrapping_mul(P32_1)
+}
+
+fn round64(acc: u64, lane: u64) -> u64 {
+    acc.wrapping_add(lane.wrapping_mul(P64_2))
+        .rotate_left(31)
+        .wrapping_mul(P64_1)
+}
+
+fn merge64(acc: u64, v: u64) -> u64 {
+    (acc ^ round64(0, v))
+        .wrapping_mul(P64_1)
+        .wrapping_add(P64_4)
+}
+
+/// xxHash32 state
+pub struct State32 {
+    seed: u32,
+    v: [u32; 4],
+    len: u64,
+    // the input past the last whole stripe of 16 bytes
+    mem: [u8; 16],
+    used: usize,
+}
+
+impl State32 {
+    /// Create a new state
+    pub fn new() -> State32 {
+        State32::with_seed(0)
+    }
+
+    /// Create a new state for the given seed
+    pub fn with_seed(seed: u32) -> State32 {
+        let mut state = State32 {
+            seed,
+            v: [0; 4],
+            len: 0,
+            mem: [0; 16],
+            used: 0,
+        };
+        state.reset();
+        state
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, v) in self.v.iter_mut().enumerate() {
+            *v = round32(*v, read32(&stripe[4 * i..]));
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, mut buf: &[u8]) {
+        self.len += buf.len() as u64;
+        if self.used + buf.len() < 16 {
+            self.mem[self.used..self.used + buf.len()].copy_from_slice(buf);
+            self.used += buf.len();
+            return;
+        }
+        if self.used > 0 {
+            let n = 16 - self.used;
+            self.mem[self.used..].copy_from_slice(&buf[..n]);
+            let mem = self.mem;
+            self.stripe(&mem);
+            buf = &buf[n..];
+            self.used = 0;
+        }
+        let mut stripes = buf.chunks_exact(16);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.mem[..rest.len()].copy_from_slice(rest);
+        self.used = rest.len();
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u32 {
+        let v = &self.v;
+        let mut h = if self.len >= 16 {
+            v[0].rotate_left(1)
+                .wrapping_add(v[1].rotate_left(7))
+                .wrapping_add(v[2].rotate_left(12))
+                .wrapping_add(v[3].rotate_left(18))
+        } else {
+            self.seed.wrapping_add(P32_5)
+        };
+        h = h.wrapping_add(self.len as u32);
+        let mut lanes = self.mem[..self.used].chunks_exact(4);
+        for lane in &mut lanes {
+            h = h
+                .wrapping_add(read32(lane).wrapping_mul(P32_3))
+                .rotate_left(17)
+                .wrapping_mul(P32_4);
+        }
+        for &byte in lanes.remainder() {
+            h = h
+                .wrapping_add((byte as u32).wrapping_mul(P32_5))
+                .rotate_left(11)
+                .wrapping_mul(P32_1);
+        }
+        h ^= h >> 15;
+        h = h.wrapping_mul(P32_2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(P32_3);
+        h ^ (h >> 16)
+    }
+
+    /// Reset the state, keeping the seed
+    pub fn reset(&mut self) {
+        let seed = self.seed;
+        self.v = [
+            seed.wrapping_add(P32_1).wrapping_add(P32_2),
+            seed.wrapping_add(P32_2),
+            seed,
+            seed.wrapping_sub(P32_1),
+        ];
+        self.len = 0;
+        self.used = 0;
+    }
+}
+
+impl Default for State32 {
+    fn default() -> State32 {
+        State32::new()
+    }
+}
+
+impl Checksum for State32 {
+    fn update(&mut self, buf: &[u8]) {
+        self.feed(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.result() as u64
+    }
+
+    fn reset(&mut self) {
+        State32::reset(self)
+    }
+
+    fn width(&self) -> u32 {
+        32
+    }
+}
+
+impl Hasher for State32 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.feed(bytes)
+    }
+
+    fn finish(&self) -> u64 {
+        self.result() as u64
+    }
+}
+
+/// xxHash64 state
+pub struct State64 {
+    seed: u64,
+    v: [u64; 4],
+    len: u64,
+    // the input past the last whole stripe of 32 bytes
+    mem: [u8; 32],
+    used: usize,
+}
+
+impl State64 {
+    /// Create a new state
+    pub fn new() -> State64 {
+        State64::with_seed(0)
+    }
+
+    /// Create a new state for the given seed
+    pub fn with_seed(seed: u64) -> State64 {
+        let mut state = State64 {
+            seed,
+            v: [0; 4],
+            len: 0,
+            mem: [0; 32],
+            used: 0,
+        };
+        state.reset();
+        state
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (i, v) in self.v.iter_mut().enumerate() {
+            *v = round64(*v, read64(&stripe[8 * i..]));
+        }
+    }
+
+    /// Mutate the state for given data
+    pub fn feed(&mut self, mut buf: &[u8]) {
+        self.len += buf.len() as u64;
+        if self.used + buf.len() < 32 {
+            self.mem[self.used..self.used + buf.len()].copy_from_slice(buf);
+            self.used += buf.len();
+            return;
+        }
+        if self.used > 0 {
+            let n = 32 - self.used;
+            self.mem[self.used..].copy_from_slice(&buf[..n]);
+            let mem = self.mem;
+            self.stripe(&mem);
+            buf = &buf[n..];
+            self.used = 0;
+        }
+        let mut stripes = buf.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.mem[..rest.len()].copy_from_slice(rest);
+        self.used = rest.len();
+    }
+
+    /// Get checksum
+    pub fn result(&self) -> u64 {
+        let v = &self.v;
+        let mut h = if self.len >= 32 {
+            let h = v[0]
+                .rotate_left(1)
+                .wrapping_add(v[1].rotate_left(7))
+                .wrapping_add(v[2].rotate_left(12))
+                .wrapping_add(v[3].rotate_left(18));
+            v.iter().fold(h, |h, &v| merge64(h, v))
+        } else {
+            self.seed.wrapping_add(P64_5)
+        };
+        h = h.wrapping_add(self.len);
+        let mut rest = &self.mem[..self.used];
+        while rest.len() >= 8 {
+            h = (h ^ round64(0, read64(rest)))
+                .rotate_left(27)
+                .wrapping_mul(P64_1)
+                .wrapping_add(P64_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            h = (h ^ (read32(rest) as u64).wrapping_mul(P64_1))
+                .rotate_left(23)
+                .wrapping_mul(P64_2)
+                .wrapping_add(P64_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest.iter() {
+            h = (h ^ (byte as u64).wrapping_mul(P64_5))
+                .rotate_left(11)
+                .wrapping_mul(P64_1);
+        }
+        h ^= h >> 33;
+        h = h.wrapping_mul(P64_2);
+        h ^= h >> 29;
+        h = h.wrapping_mul(P64_3);
+        h ^ (h >> 32)
+    }
+
+    /// Reset the state, keeping the seed
+    pub fn reset(&mut self) {
+        let seed = self.seed;
+        self.v = [
+            seed.wrapping_add(P64_1).wrapping_add(P64_2),
+            seed.wrapping_add(P64_2),
+            seed,
+            seed.wrapping_sub(P64_1),
+        ];
+        self.len = 0;
+        self.used = 0;
+    }
+}
+
+impl Default for State64 {
+    fn default() -> State64 {
+        State64::new()
+    }
+}
+
+impl Checksum for State64 {
+    fn update(&mut self, buf: &[u8]) {
+        self.feed(buf)
+    }
+
+    fn finalize(&self) -> u64 {
+        self.result()
+    }
+
+    fn reset(&mut self) {
+        State64::reset(self)
+    }
+
+    fn width(&self) -> u32 {
+        64
+    }
+}
+
+impl Hasher for State64 {
+    fn write(&mut self, bytes: &[u8]) {
+        self.feed(bytes)
+    }
+
+    fn finish(&self) -> u64 {
+        self.result()
+    }
+}
+
+/// Get the xxHash32 of the given data at once
+pub fn xxh32(buf: &[u8], seed: u32) -> u32 {
+    let mut state = State32::with_seed(seed);
+    state.feed(buf);
+    state.result()
+}
+
+/// Get the xxHash64 of the given data at once
+pub fn xxh64(buf: &[u8], seed: u64) -> u64 {
+    let mut state = State64::with_seed(seed);
+    state.feed(buf);
+    state.result()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{xxh32, xxh64, State32, State64};
+
+    #[test]
+    fn known() {
+        assert_eq!(xxh32(b"", 0), 0x02cc5d05);
+        assert_eq!(xxh32(b"", 1), 0x0b2cb792);
+        assert_eq!(xxh32(b"abc", 0), 0x32d153ff);
+        let spam = b"Nobody inspects the spammish repetition";
+        assert_eq!(xxh32(spam, 0), 0xe2293b2f);
+        assert_eq!(xxh64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(xxh64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(xxh64(spam, 0), 0xfbcea83c8a378bf1);
+    }
+
+    #[test]
+    fn pieces() {
+        let data = &include_bytes!("../data/test.txt")[..];
+        for &seed in [0, 1, 0x9e3779b1].iter() {
+            let mut s32 = State32::with_seed(seed as u32);
+            let mut s64 = State64::with_seed(seed);
+            for piece in data.chunks(7) {
+                s32.feed(piece);
+                s64.feed(piece);
+            }
+            assert_eq!(s32.result(), xxh32(data, seed as u32));
+            assert_eq!(s64.result(), xxh64(data, seed));
+            s32.reset();
+            s64.reset();
+            assert_eq!(s32.result(), xxh32(b"", seed as u32));
+            assert_eq!(s64.result(), xxh64(b"", seed));
+        }
+    }
+}
@@ -0,0 +1,879 @@
+//! DEFLATE encoder
+//!
+//! The encoder keeps a sliding window of the input, finds repeated strings in
+//! it through hash chains, and gathers the resulting literal/match tokens into
+//! blocks. Each block is written out in whichever representation (stored, fixed
+//! Huffman or dynamic Huffman) turns out to be the smallest.
+
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+
+use super::optimal;
+use super::{
+    EXTRABITS, EXTRADBITS, EXTRADIST, EXTRALENS, FIXED_LIT_LENGTHS, HISTORY, MAXBITS, ORDER,
+};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// amount of input kept ahead of the current position so that a whole match
+// can always be compared
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const WMASK: usize = HISTORY - 1;
+// number of tokens gathered before a block is emitted
+const BLOCK_TOKENS: usize = 16 * 1024 - 1;
+const MAX_STORED: usize = 65535;
+// code length codes are limited to 7 bits by the format
+const MAXCLBITS: usize = 7;
+const NIL: u32 = 0;
+
+/// A single LZ77 output symbol
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token {
+    /// A byte copied verbatim
+    Literal(u8),
+    /// A copy of `len` bytes found `dist` bytes back
+    Match(u16, u16),
+}
+
+/// Accumulates bits LSB first, the way DEFLATE packs them into bytes
+pub struct BitWriter {
+    pub out: Vec<u8>,
+    bitbuf: u64,
+    bitcnt: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            bitbuf: 0,
+            bitcnt: 0,
+        }
+    }
+
+    /// Appends the low `cnt` bits of `value`
+    pub fn bits(&mut self, value: u32, cnt: usize) {
+        self.bitbuf |= (value as u64) << self.bitcnt;
+        self.bitcnt += cnt;
+        while self.bitcnt >= 8 {
+            self.out.push(self.bitbuf as u8);
+            self.bitbuf >>= 8;
+            self.bitcnt -= 8;
+        }
+    }
+
+    /// Number of bits pending in the last, partially filled byte
+    pub fn pending(&self) -> usize {
+        self.bitcnt
+    }
+
+    /// Pads the stream with zero bits up to the next byte boundary
+    pub fn align(&mut self) {
+        if self.bitcnt > 0 {
+            self.out.push(self.bitbuf as u8);
+            self.bitbuf = 0;
+            self.bitcnt = 0;
+        }
+    }
+}
+
+/// Returns the length code (0-based, to be added to 257) for a match length
+pub fn length_code(len: usize) -> usize {
+    match EXTRALENS.binary_search(&(len as u16)) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    }
+}
+
+/// Returns the distance code for a match distance
+pub fn dist_code(dist: usize) -> usize {
+    match EXTRADIST.binary_search(&(dist as u16)) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    }
+}
+
+/// Computes Huffman code lengths for the given symbol frequencies, with no
+/// code longer than `limit` bits. At least two symbols always get a code, as
+/// some decoders refuse trees made of a single code.
+pub fn build_lengths(freqs: &[u32], limit: usize, lens: &mut [u8]) {
+    for len in lens.iter_mut() {
+        *len = 0;
+    }
+    let mut syms: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] != 0).collect();
+    let mut filler = 0;
+    while syms.len() < 2 {
+        if freqs[filler] == 0 {
+            syms.push(filler);
+        }
+        filler += 1;
+    }
+    if syms.len() == 2 {
+        lens[syms[0]] = 1;
+        lens[syms[1]] = 1;
+        return;
+    }
+
+    // Plain Huffman construction, leaves first and internal nodes after them
+    let n = syms.len();
+    let mut parent = vec![0; 2 * n - 1];
+    let mut heap = BinaryHeap::with_capacity(n);
+    for (i, &sym) in syms.iter().enumerate() {
+        heap.push((
+            cmp::Reverse(cmp::max(freqs[sym], 1) as u64),
+            cmp::Reverse(i),
+        ));
+    }
+    let mut next = n;
+    while heap.len() > 1 {
+        let (cmp::Reverse(wa), cmp::Reverse(a)) = heap.pop().unwrap();
+        let (cmp::Reverse(wb), cmp::Reverse(b)) = heap.pop().unwrap();
+        parent[a] = next;
+        parent[b] = next;
+        heap.push((cmp::Reverse(wa + wb), cmp::Reverse(next)));
+        next += 1;
+    }
+    let root = next - 1;
+    let mut depth = vec![0usize; 2 * n - 1];
+    for i in (0..root).rev() {
+        depth[i] = depth[parent[i]] + 1;
+    }
+
+    // Clamp the overlong codes, then pay for it by lengthening the rarest of
+    // the codes that still have room until the Kraft sum fits again.
+    let full = 1u64 << limit;
+    let mut kraft = 0u64;
+    for d in depth[..n].iter_mut() {
+        *d = cmp::min(*d, limit);
+        kraft += 1 << (limit - *d);
+    }
+    while kraft > full {
+        let mut best = n;
+        for i in 0..n {
+            if depth[i] < limit
+                && (best == n
+                    || depth[i] > depth[best]
+                    || (depth[i] == depth[best] && freqs[syms[i]] < freqs[syms[best]]))
+            {
+                best = i;
+            }
+        }
+        depth[best] += 1;
+        kraft -= 1 << (limit - depth[best]);
+    }
+    // Any slack left over goes to the most frequent symbols
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| freqs[syms[b]].cmp(&freqs[syms[a]]));
+    for &i in order.iter() {
+        while depth[i] > 1 && kraft + (1 << (limit - depth[i])) <= full {
+            kraft += 1 << (limit - depth[i]);
+            depth[i] -= 1;
+        }
+    }
+
+    for i in 0..n {
+        lens[syms[i]] = depth[i] as u8;
+    }
+}
+
+/// Assigns canonical codes to the given lengths. The codes are returned bit
+/// reversed, ready to be sent LSB first.
+pub fn build_codes(lens: &[u8], codes: &mut [u16]) {
+    let mut count = [0u16; MAXBITS + 1];
+    for &len in lens.iter() {
+        count[len as usize] += 1;
+    }
+    count[0] = 0;
+    let mut next = [0u16; MAXBITS + 1];
+    let mut code = 0;
+    for bits in 1..(MAXBITS + 1) {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    for (sym, &len) in lens.iter().enumerate() {
+        if len != 0 {
+            codes[sym] = next[len as usize].reverse_bits() >> (16 - len as usize);
+            next[len as usize] += 1;
+        }
+    }
+}
+
+/// Run-length encodes a sequence of code lengths with the code length
+/// alphabet, producing `(symbol, extra bits value)` pairs.
+fn run_lengths(lens: &[u8], out: &mut Vec<(u8, u8)>) {
+    let mut i = 0;
+    while i < lens.len() {
+        let len = lens[i];
+        let mut run = 1;
+        while i + run < lens.len() && lens[i + run] == len {
+            run += 1;
+        }
+        i += run;
+        if len == 0 {
+            while run >= 11 {
+                let n = cmp::min(run, 138);
+                out.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((len, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = cmp::min(run, 6);
+                out.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        for _ in 0..run {
+            out.push((len, 0));
+        }
+    }
+}
+
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    (FIXED_LIT_LENGTHS, [5; 30])
+}
+
+/// Literal/length and distance symbol statistics of a block
+#[derive(Clone)]
+pub struct Stats {
+    pub lit: [u32; 286],
+    pub dist: [u32; 30],
+    /// Total amount of extra bits following the length and distance codes
+    pub extra: u64,
+}
+
+impl Stats {
+    pub fn new(tokens: &[Token]) -> Stats {
+        let mut stats = Stats {
+            lit: [0; 286],
+            dist: [0; 30],
+            extra: 0,
+        };
+        for token in tokens.iter() {
+            stats.add(token);
+        }
+        stats.lit[256] = 1;
+        stats
+    }
+
+    /// Counts one more token
+    pub fn add(&mut self, token: &Token) {
+        match *token {
+            Token::Literal(b) => self.lit[b as usize] += 1,
+            Token::Match(len, dist) => {
+                let lc = length_code(len as usize);
+                let dc = dist_code(dist as usize);
+                self.lit[257 + lc] += 1;
+                self.dist[dc] += 1;
+                self.extra += (EXTRABITS[lc] + EXTRADBITS[dc]) as u64;
+            }
+        }
+    }
+
+    /// Takes out the tokens counted by `other`, which must all be counted
+    /// here as well. The end of block stays counted.
+    pub fn remove(&mut self, other: &Stats) {
+        for (n, &m) in self.lit.iter_mut().zip(other.lit.iter()) {
+            *n -= m;
+        }
+        for (n, &m) in self.dist.iter_mut().zip(other.dist.iter()) {
+            *n -= m;
+        }
+        self.extra -= other.extra;
+        self.lit[256] = 1;
+    }
+
+    /// Bits needed for the block contents when coded with the given lengths
+    pub fn cost(&self, lit: &[u8], dist: &[u8]) -> u64 {
+        let l: u64 = self
+            .lit
+            .iter()
+            .zip(lit.iter())
+            .map(|(&f, &n)| f as u64 * n as u64)
+            .sum();
+        let d: u64 = self
+            .dist
+            .iter()
+            .zip(dist.iter())
+            .map(|(&f, &n)| f as u64 * n as u64)
+            .sum();
+        l + d + self.extra
+    }
+
+    /// Estimates the size in bits of a block with these statistics, which
+    /// decodes to `len` bytes, in the cheapest of the three block
+    /// representations
+    pub fn block_cost(&self, len: usize) -> u64 {
+        let dynamic = Dynamic::new(self);
+        let (flit, fdist) = fixed_lengths();
+        let dynamic_cost = 3 + dynamic.cost() + self.cost(&dynamic.lit, &dynamic.dist);
+        let fixed_cost = 3 + self.cost(&flit, &fdist);
+        cmp::min(cmp::min(dynamic_cost, fixed_cost), stored_cost(len, 0))
+    }
+}
+
+/// A dynamic Huffman block header, ready to be written out
+struct Dynamic {
+    lit: [u8; 286],
+    dist: [u8; 30],
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    cl: [u8; 19],
+    runs: Vec<(u8, u8)>,
+}
+
+impl Dynamic {
+    fn new(stats: &Stats) -> Dynamic {
+        let mut lit = [0; 286];
+        let mut dist = [0; 30];
+        build_lengths(&stats.lit, MAXBITS, &mut lit);
+        build_lengths(&stats.dist, MAXBITS, &mut dist);
+        let hlit = cmp::max(257, lit.iter().rposition(|&l| l != 0).unwrap() + 1);
+        let hdist = dist.iter().rposition(|&l| l != 0).unwrap() + 1;
+
+        let mut all = Vec::with_capacity(hlit + hdist);
+        all.extend_from_slice(&lit[..hlit]);
+        all.extend_from_slice(&dist[..hdist]);
+        let mut runs = Vec::new();
+        run_lengths(&all, &mut runs);
+        let mut freqs = [0; 19];
+        for &(sym, _) in runs.iter() {
+            freqs[sym as usize] += 1;
+        }
+        let mut cl = [0; 19];
+        build_lengths(&freqs, MAXCLBITS, &mut cl);
+        let hclen = cmp::max(4, ORDER.iter().rposition(|&i| cl[i] != 0).unwrap() + 1);
+
+        Dynamic {
+            lit,
+            dist,
+            hlit,
+            hdist,
+            hclen,
+            cl,
+            runs,
+        }
+    }
+
+    /// Size of the header in bits
+    fn cost(&self) -> u64 {
+        let mut bits = 5 + 5 + 4 + 3 * self.hclen as u64;
+        for &(sym, _) in self.runs.iter() {
+            bits += self.cl[sym as usize] as u64;
+            bits += match sym {
+                16 => 2,
+                17 => 3,
+                18 => 7,
+                _ => 0,
+            };
+        }
+        bits
+    }
+
+    fn write(&self, bits: &mut BitWriter) {
+        bits.bits((self.hlit - 257) as u32, 5);
+        bits.bits((self.hdist - 1) as u32, 5);
+        bits.bits((self.hclen - 4) as u32, 4);
+        for &i in ORDER[..self.hclen].iter() {
+            bits.bits(self.cl[i] as u32, 3);
+        }
+        let mut codes = [0; 19];
+        build_codes(&self.cl, &mut codes);
+        for &(sym, extra) in self.runs.iter() {
+            bits.bits(codes[sym as usize] as u32, self.cl[sym as usize] as usize);
+            match sym {
+                16 => bits.bits(extra as u32, 2),
+                17 => bits.bits(extra as u32, 3),
+                18 => bits.bits(extra as u32, 7),
+                _ => (),
+            }
+        }
+    }
+}
+
+fn write_tokens(bits: &mut BitWriter, tokens: &[Token], lit: &[u8], dist: &[u8]) {
+    let mut lcodes = [0; 288];
+    let mut dcodes = [0; 30];
+    build_codes(lit, &mut lcodes);
+    build_codes(dist, &mut dcodes);
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(b) => bits.bits(lcodes[b as usize] as u32, lit[b as usize] as usize),
+            Token::Match(len, d) => {
+                let lc = length_code(len as usize);
+                bits.bits(lcodes[257 + lc] as u32, lit[257 + lc] as usize);
+                bits.bits((len - EXTRALENS[lc]) as u32, EXTRABITS[lc] as usize);
+                let dc = dist_code(d as usize);
+                bits.bits(dcodes[dc] as u32, dist[dc] as usize);
+                bits.bits((d - EXTRADIST[dc]) as u32, EXTRADBITS[dc] as usize);
+            }
+        }
+    }
+    bits.bits(lcodes[256] as u32, lit[256] as usize);
+}
+
+/// Writes the data of `raw` as a sequence of stored blocks
+pub fn write_stored(bits: &mut BitWriter, raw: &[u8], last: bool) {
+    let mut chunks = raw.chunks(MAX_STORED).peekable();
+    loop {
+        let chunk = chunks.next().unwrap_or(&[]);
+        let fin = last && chunks.peek().is_none();
+        bits.bits(fin as u32, 1);
+        bits.bits(0, 2);
+        bits.align();
+        let len = chunk.len() as u16;
+        bits.out
+            .extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, !(len >> 8) as u8]);
+        bits.out.extend_from_slice(chunk);
+        if chunks.peek().is_none() {
+            break;
+        }
+    }
+}
+
+// Size in bits of `len` bytes written as stored blocks, when the stream has
+// `pending` bits in its last byte
+fn stored_cost(len: usize, pending: usize) -> u64 {
+    let chunks = cmp::max(1, len.div_ceil(MAX_STORED)) as u64;
+    let pad = (8 - (pending + 3) % 8) % 8;
+    3 + pad as u64 + 32 + 8 * len as u64 + (chunks - 1) * (8 + 32)
+}
+
+/// Estimates the size in bits of a block holding `tokens`, which decode to
+/// `len` bytes, in the cheapest of the three block representations
+pub fn block_cost(tokens: &[Token], len: usize) -> u64 {
+    Stats::new(tokens).block_cost(len)
+}
+
+/// Writes a block holding `tokens`, which decode to `raw`, in the cheapest of
+/// the three block representations.
+pub fn write_block(bits: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let stats = Stats::new(tokens);
+    let dynamic = Dynamic::new(&stats);
+    let (flit, fdist) = fixed_lengths();
+
+    let dynamic_cost = 3 + dynamic.cost() + stats.cost(&dynamic.lit, &dynamic.dist);
+    let fixed_cost = 3 + stats.cost(&flit, &fdist);
+    let stored_cost = stored_cost(raw.len(), bits.pending());
+
+    if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+        write_stored(bits, raw, last);
+    } else if fixed_cost <= dynamic_cost {
+        bits.bits(last as u32, 1);
+        bits.bits(1, 2);
+        write_tokens(bits, tokens, &flit, &fdist);
+    } else {
+        bits.bits(last as u32, 1);
+        bits.bits(2, 2);
+        dynamic.write(bits);
+        write_tokens(bits, tokens, &dynamic.lit, &dynamic.dist);
+    }
+}
+
+/// Matcher tuning for a compression level, after zlib's configuration table
+struct Config {
+    /// Once a match this long is in hand, only a quarter of the chain is
+    /// searched for a better one
+    good: usize,
+    /// Lazy levels: no lookahead is done past a match this long. Greedy
+    /// levels: longer matches don't get their strings inserted in the hash.
+    lazy: usize,
+    /// Searching stops as soon as a match this long is found
+    nice: usize,
+    /// Maximum number of hash chain entries to probe
+    chain: usize,
+    strategy: Strategy,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Strategy {
+    Stored,
+    Greedy,
+    Lazy,
+    Optimal,
+}
+
+#[rustfmt::skip]
+static CONFIG: [Config; 10] = [
+    Config { good: 0, lazy: 0, nice: 0, chain: 0, strategy: Strategy::Stored },
+    Config { good: 4, lazy: 4, nice: 8, chain: 1, strategy: Strategy::Greedy },
+    Config { good: 4, lazy: 5, nice: 16, chain: 8, strategy: Strategy::Greedy },
+    Config { good: 4, lazy: 6, nice: 32, chain: 32, strategy: Strategy::Greedy },
+    Config { good: 4, lazy: 4, nice: 16, chain: 16, strategy: Strategy::Lazy },
+    Config { good: 8, lazy: 16, nice: 32, chain: 32, strategy: Strategy::Lazy },
+    Config { good: 8, lazy: 16, nice: 128, chain: 128, strategy: Strategy::Lazy },
+    Config { good: 8, lazy: 32, nice: 128, chain: 256, strategy: Strategy::Lazy },
+    Config { good: 32, lazy: 128, nice: 258, chain: 1024, strategy: Strategy::Lazy },
+    Config { good: 32, lazy: 258, nice: 258, chain: 4096, strategy: Strategy::Lazy },
+];
+
+static OPTIMAL: Config = Config {
+    good: 0,
+    lazy: 0,
+    nice: 0,
+    chain: 0,
+    strategy: Strategy::Optimal,
+};
+
+// amount of input parsed at once by the optimal parser
+const MASTER_BLOCK: usize = 1 << 20;
+
+/// The compression level used by `Encoder::new`
+pub const DEFAULT_LEVEL: u8 = 6;
+
+// Lazy levels discard 3-byte matches further away than this, as they usually
+// cost more than the literals they replace
+const TOO_FAR: usize = 4096;
+
+/// The structure that is used to produce a DEFLATE stream. This wraps an
+/// internal writer which receives the compressed data.
+///
+/// Calling `flush` terminates the current block and appends an empty stored
+/// block, so that everything written so far can be decoded (a "sync flush").
+/// The stream is only complete once `finish` is called.
+pub struct Encoder<W> {
+    w: W,
+    bits: BitWriter,
+    config: &'static Config,
+    iterations: usize,
+    // farthest back a match may reach
+    window: usize,
+
+    // sliding window: up to `HISTORY` bytes of history followed by the input
+    // which hasn't been encoded yet
+    data: Vec<u8>,
+    pos: usize,
+    block_start: usize,
+    tokens: Vec<Token>,
+    // match found at `pos` while looking ahead, not emitted yet
+    pending: Option<(usize, usize)>,
+
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    // every position below this one has been linked into the hash chains
+    inserted: usize,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new flate encoder which will have its output written to the
+    /// given output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, DEFAULT_LEVEL)
+    }
+
+    /// Creates a new flate encoder with the given compression level, from 0
+    /// (no compression, stored blocks only) through 1 (fastest) to 9 (best
+    /// compression). Levels 1 to 3 match greedily, higher levels search
+    /// longer hash chains and evaluate matches lazily.
+    pub fn with_level(w: W, level: u8) -> Encoder<W> {
+        assert!(level <= 9, "invalid compression level {}", level);
+        Encoder {
+            w,
+            bits: BitWriter::new(),
+            config: &CONFIG[level as usize],
+            iterations: 0,
+            window: HISTORY,
+            data: Vec::with_capacity(2 * HISTORY + MIN_LOOKAHEAD),
+            pos: 0,
+            block_start: 0,
+            tokens: Vec::with_capacity(BLOCK_TOKENS),
+            pending: None,
+            head: vec![NIL; HASH_SIZE],
+            prev: vec![NIL; HISTORY],
+            inserted: 0,
+        }
+    }
+
+    /// Creates a new flate encoder which looks for the smallest encoding it
+    /// can find, no matter the time spent. The input is parsed optimally
+    /// under a model of the bit cost of each symbol, which is refined over
+    /// `iterations` rounds (15 is a good default), and split into blocks
+    /// where that lowers the estimated size. This is far slower than any
+    /// compression level and meant for data compressed once and decoded many
+    /// times; the output is a regular DEFLATE stream.
+    pub fn optimal(w: W, iterations: usize) -> Encoder<W> {
+        let mut e = Encoder::with_level(w, 9);
+        e.config = &OPTIMAL;
+        e.iterations = iterations;
+        e
+    }
+
+    /// Creates a new flate encoder with the given compression level which
+    /// starts out with `dict` as history, so that the data can refer back to
+    /// it. This pays off for short inputs resembling the dictionary, but the
+    /// stream can only be decoded with the same dictionary. Only the last 32K
+    /// of the dictionary are of use.
+    pub fn with_dictionary(w: W, level: u8, dict: &[u8]) -> Encoder<W> {
+        let mut e = Encoder::with_level(w, level);
+        let dict = &dict[dict.len().saturating_sub(HISTORY)..];
+        e.data.extend_from_slice(dict);
+        e.pos = dict.len();
+        e.block_start = dict.len();
+        if e.config.strategy != Strategy::Stored {
+            for p in 0..dict.len() {
+                e.insert(p);
+            }
+        }
+        e
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let d = &self.data[pos..];
+        (((d[0] as usize) << 10) ^ ((d[1] as usize) << 5) ^ (d[2] as usize)) & (HASH_SIZE - 1)
+    }
+
+    // Links `pos` into its hash chain, returning the chain of earlier
+    // positions sharing its hash
+    fn insert(&mut self, pos: usize) -> u32 {
+        if pos + MIN_MATCH > self.data.len() {
+            return NIL;
+        }
+        if pos < self.inserted {
+            return self.prev[pos & WMASK];
+        }
+        let h = self.hash(pos);
+        let head = self.head[h];
+        self.prev[pos & WMASK] = head;
+        self.head[h] = pos as u32 + 1;
+        self.inserted = pos + 1;
+        head
+    }
+
+    // Walks the hash chain starting at `head` looking for a match of the data
+    // at `pos` longer than `prev_len`
+    fn longest_match(&self, pos: usize, mut head: u32, prev_len: usize) -> (usize, usize) {
+        let max = cmp::min(MAX_MATCH, self.data.len() - pos);
+        let nice = cmp::min(self.config.nice, max);
+        let mut best = (prev_len, 0);
+        let mut chain = self.config.chain;
+        if prev_len >= self.config.good {
+            chain >>= 2;
+        }
+        if max <= prev_len {
+            return (0, 0);
+        }
+        while head != NIL && chain > 0 {
+            let cand = head as usize - 1;
+            if cand >= pos || pos - cand > self.window {
+                break;
+            }
+            if self.data[cand + best.0] == self.data[pos + best.0] {
+                let len = self.data[cand..cand + max]
+                    .iter()
+                    .zip(self.data[pos..pos + max].iter())
+                    .take_while(|&(a, b)| a == b)
+                    .count();
+                if len > best.0 {
+                    best = (len, pos - cand);
+                    if len >= nice {
+                        break;
+                    }
+                }
+            }
+            head = self.prev[cand & WMASK];
+            chain -= 1;
+        }
+        if best.1 == 0 {
+            (0, 0)
+        } else {
+            best
+        }
+    }
+
+    // Drops the oldest `HISTORY` bytes of the window, once they can neither be
+    // referenced nor be needed for a stored block
+    fn slide(&mut self) {
+        if self.pos < 2 * HISTORY || self.block_start < HISTORY {
+            return;
+        }
+        self.data.drain(..HISTORY);
+        self.pos -= HISTORY;
+        self.block_start -= HISTORY;
+        self.inserted = self.inserted.saturating_sub(HISTORY);
+        for p in self.head.iter_mut().chain(self.prev.iter_mut()) {
+            *p = if *p as usize > HISTORY {
+                *p - HISTORY as u32
+            } else {
+                NIL
+            };
+        }
+    }
+
+    fn literal(&mut self) {
+        self.tokens.push(Token::Literal(self.data[self.pos]));
+        self.pos += 1;
+    }
+
+    fn matched(&mut self, len: usize, dist: usize) {
+        self.tokens.push(Token::Match(len as u16, dist as u16));
+        if self.config.strategy == Strategy::Lazy || len <= self.config.lazy {
+            for p in (self.pos + 1)..(self.pos + len) {
+                self.insert(p);
+            }
+        }
+        self.pos += len;
+    }
+
+    // Turns the pending input into tokens. Unless `flush` is set, enough input
+    // is held back to be able to find full-length matches later on.
+    fn deflate(&mut self, flush: bool) -> io::Result<()> {
+        loop {
+            let avail = self.data.len() - self.pos;
+            if avail == 0 || (!flush && avail < MIN_LOOKAHEAD) {
+                return Ok(());
+            }
+            match self.config.strategy {
+                Strategy::Stored => {
+                    self.pos = self.data.len();
+                    if flush || self.pos - self.block_start >= MAX_STORED {
+                        self.block(false)?;
+                    }
+                    return Ok(());
+                }
+                Strategy::Optimal => {
+                    // Whole master blocks are parsed as soon as they're
+                    // complete, the rest waits for a flush or `finish`
+                    if avail > MASTER_BLOCK {
+                        self.pos += MASTER_BLOCK;
+                        self.block(false)?;
+                        continue;
+                    }
+                    if flush {
+                        self.pos = self.data.len();
+                    }
+                    return Ok(());
+                }
+                Strategy::Greedy => {
+                    let head = self.insert(self.pos);
+                    let (len, dist) = self.longest_match(self.pos, head, MIN_MATCH - 1);
+                    if len >= MIN_MATCH {
+                        self.matched(len, dist);
+                    } else {
+                        self.literal();
+                    }
+                }
+                Strategy::Lazy => {
+                    let (len, dist) = match self.pending.take() {
+                        Some(m) => m,
+                        None => {
+                            let head = self.insert(self.pos);
+                            self.longest_match(self.pos, head, MIN_MATCH - 1)
+                        }
+                    };
+                    if len < MIN_MATCH || (len == MIN_MATCH && dist > TOO_FAR) {
+                        self.literal();
+                    } else if len < self.config.lazy && avail > 1 {
+                        // Emit the match only if the next position doesn't
+                        // start a longer one
+                        let head = self.insert(self.pos + 1);
+                        let next = self.longest_match(self.pos + 1, head, len);
+                        if next.0 > len {
+                            self.literal();
+                            self.pending = Some(next);
+                        } else {
+                            self.matched(len, dist);
+                        }
+                    } else {
+                        self.matched(len, dist);
+                    }
+                }
+            }
+            if self.tokens.len() >= BLOCK_TOKENS {
+                self.block(false)?;
+            }
+        }
+    }
+
+    // Emits the data since the last block as one block
+    fn block(&mut self, last: bool) -> io::Result<()> {
+        let raw = &self.data[self.block_start..self.pos];
+        match self.config.strategy {
+            Strategy::Stored => write_stored(&mut self.bits, raw, last),
+            Strategy::Optimal => {
+                let history = self.block_start.saturating_sub(HISTORY);
+                optimal::write_blocks(
+                    &mut self.bits,
+                    &self.data[history..self.pos],
+                    self.block_start - history,
+                    self.window,
+                    self.iterations,
+                    last,
+                );
+            }
+            _ => write_block(&mut self.bits, &self.tokens, raw, last),
+        }
+        if last {
+            self.bits.align();
+        }
+        self.tokens.clear();
+        self.block_start = self.pos;
+        self.slide();
+        self.w.write_all(&self.bits.out)?;
+        self.bits.out.clear();
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.deflate(true).and_then(|_| self.block(true));
+        (self.w, result)
+    }
+
+    // Keeps matches within `size` bytes back, for decoders with a smaller
+    // window than the usual 32K
+    pub(crate) fn limit_window(&mut self, size: usize) {
+        self.window = cmp::min(size, HISTORY);
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    // Encodes all of the input so far, ending with an empty stored block so
+    // that the output reaches a byte boundary, without flushing the writer
+    pub(crate) fn sync_flush(&mut self) -> io::Result<()> {
+        self.deflate(true)?;
+        if self.pos != self.block_start {
+            self.block(false)?;
+        }
+        write_stored(&mut self.bits, &[], false);
+        self.w.write_all(&self.bits.out)?;
+        self.bits.out.clear();
+        Ok(())
+    }
+
+    // Ends the data with a sync flush instead of the final block, so that
+    // the blocks of another encoder can follow
+    pub(crate) fn finish_flushed(mut self) -> (W, io::Result<()>) {
+        let result = self.flush();
+        (self.w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for chunk in buf.chunks(HISTORY) {
+            self.data.extend_from_slice(chunk);
+            self.deflate(false)?;
+            self.slide();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sync_flush()?;
+        self.w.flush()
+    }
+}
@@ -0,0 +1,246 @@
+//! Random access into DEFLATE streams, after zlib's zran example
+//!
+//! Decoding can't start just anywhere in a DEFLATE stream, it has to start
+//! at the beginning of a block, with the 32K of data preceding that point at
+//! hand for back references. A pass over the whole stream records such
+//! checkpoints every so often, after which the data at any position can be
+//! had by decoding from the checkpoint before it.
+
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use super::{Counted, Inflater, Input, HISTORY};
+
+/// A point at which decoding of a stream can start
+pub struct Checkpoint {
+    /// Offset in bits of the start of a block in the compressed data
+    pub bit_offset: u64,
+    /// Offset of the data decoded from that block on in the uncompressed data
+    pub offset: u64,
+    /// The uncompressed data preceding the block, up to 32K of it
+    pub window: Vec<u8>,
+}
+
+/// Checkpoints spread over a DEFLATE stream, see `IndexedDecoder`
+pub struct Index {
+    points: Vec<Checkpoint>,
+    length: u64,
+}
+
+impl Index {
+    /// Decodes the DEFLATE stream that `r` is positioned at the start of,
+    /// e.g. right behind a zlib header, recording a checkpoint whenever
+    /// `span` bytes of uncompressed data have gone by since the last one. A
+    /// checkpoint costs 32K of memory, and seeking costs decoding `span`
+    /// bytes on average.
+    pub fn build<R: Read + Seek>(mut r: R, span: u64) -> io::Result<Index> {
+        let start = r.stream_position()?;
+        let mut input = Input::new();
+        let mut src = Counted {
+            inner: input.source(&mut r),
+            count: 0,
+        };
+        let mut inflater = Inflater::new();
+        let mut points = vec![Checkpoint {
+            bit_offset: start * 8,
+            offset: 0,
+            window: Vec::new(),
+        }];
+        let mut length = 0;
+        while !inflater.eof() {
+            if inflater.at_boundary() && length - points[points.len() - 1].offset >= span {
+                points.push(Checkpoint {
+                    bit_offset: start * 8 + inflater.bit_position(src.count),
+                    offset: length,
+                    window: inflater.history(),
+                });
+            }
+            length += inflater.inflate(&mut src, HISTORY)? as u64;
+        }
+        Ok(Index { points, length })
+    }
+
+    /// Length of the uncompressed data
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns whether the stream holds no data at all
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// The recorded checkpoints, by increasing offset
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.points
+    }
+
+    // The checkpoint to start from to get at the data at `pos`
+    fn find(&self, pos: u64) -> usize {
+        match self.points.binary_search_by_key(&pos, |p| p.offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        }
+    }
+}
+
+/// A decoder of a DEFLATE stream in a seekable reader which can seek in the
+/// uncompressed data, with the help of an `Index` of the stream
+pub struct IndexedDecoder<R> {
+    r: R,
+    index: Index,
+    input: Input,
+    inflater: Inflater,
+    pos: u64,
+    // whether the decoder is set up to decode from `pos`
+    ready: bool,
+}
+
+impl<R: Read + Seek> IndexedDecoder<R> {
+    /// Creates a new decoder reading from `r`, which the index has been
+    /// built on
+    pub fn new(r: R, index: Index) -> IndexedDecoder<R> {
+        IndexedDecoder {
+            r,
+            index,
+            input: Input::new(),
+            inflater: Inflater::new(),
+            pos: 0,
+            ready: false,
+        }
+    }
+
+    /// The index this decoder uses
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r
+    }
+
+    // Sets up decoding from the checkpoint before `pos`, and decodes up to it
+    fn restart(&mut self) -> io::Result<()> {
+        let point = &self.index.points[self.index.find(self.pos)];
+        self.r.seek(SeekFrom::Start(point.bit_offset / 8))?;
+        self.input.clear();
+        self.inflater.reset();
+        self.inflater.set_dictionary(&point.window);
+        let bits = (point.bit_offset % 8) as usize;
+        if bits != 0 {
+            let mut byte = [0];
+            self.input.source(&mut self.r).read_exact(&mut byte)?;
+            self.inflater.prime(byte[0] >> bits, 8 - bits);
+        }
+        self.ready = true;
+        let skip = self.pos - point.offset;
+        self.skip(skip)
+    }
+
+    // Decodes and drops `n` bytes
+    fn skip(&mut self, mut n: u64) -> io::Result<()> {
+        let mut buf = [0; 8192];
+        while n > 0 {
+            let len = cmp::min(n, buf.len() as u64) as usize;
+            let mut src = self.input.source(&mut self.r);
+            match self.inflater.read(&mut src, &mut buf[..len])? {
+                0 => break,
+                len => n -= len as u64,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.index.length {
+            return Ok(0);
+        }
+        if !self.ready {
+            self.restart()?;
+        }
+        let mut src = self.input.source(&mut self.r);
+        let n = self.inflater.read(&mut src, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedDecoder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.index.length.checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        let target = match target {
+            Some(target) => target,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                ))
+            }
+        };
+        // Decoding on is cheaper than restarting when there's no checkpoint
+        // in between
+        if self.ready
+            && target > self.pos
+            && target <= self.index.length
+            && self.index.find(target) == self.index.find(self.pos)
+        {
+            let skip = target - self.pos;
+            self.pos = target;
+            self.skip(skip)?;
+        } else if target != self.pos {
+            self.pos = target;
+            self.ready = false;
+        }
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Index, IndexedDecoder};
+    use rand::random;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    #[test]
+    fn random_access() {
+        let reference = &include_bytes!("../data/test.large")[..];
+        // the DEFLATE data starts behind the 2 byte zlib header
+        let mut r = Cursor::new(&include_bytes!("../data/test.large.z.5")[..]);
+        r.seek(SeekFrom::Start(2)).unwrap();
+        let index = Index::build(&mut r, 1 << 20).unwrap();
+        assert_eq!(index.len(), reference.len() as u64);
+        assert!(index.checkpoints().len() > 5);
+        assert!(index.checkpoints().iter().any(|p| p.bit_offset % 8 != 0));
+
+        let mut d = IndexedDecoder::new(r, index);
+        let mut buf = [0; 1000];
+        for _ in 0..20 {
+            let pos = random::<usize>() % reference.len();
+            assert_eq!(d.seek(SeekFrom::Start(pos as u64)).unwrap(), pos as u64);
+            let n = d.read(&mut buf).unwrap();
+            assert!(n > 0);
+            assert!(buf[..n] == reference[pos..pos + n]);
+        }
+
+        // short seeks forward, and backwards from the end
+        d.seek(SeekFrom::Start(100)).unwrap();
+        d.read_exact(&mut buf).unwrap();
+        assert_eq!(d.seek(SeekFrom::Current(5000)).unwrap(), 6100);
+        d.read_exact(&mut buf).unwrap();
+        assert!(buf[..] == reference[6100..7100]);
+        d.seek(SeekFrom::End(-10)).unwrap();
+        let mut tail = Vec::new();
+        d.read_to_end(&mut tail).unwrap();
+        assert!(tail[..] == reference[reference.len() - 10..]);
+        assert!(d
+            .seek(SeekFrom::Current(-1 - reference.len() as i64))
+            .is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Joining DEFLATE streams without decoding and encoding them again, after
+//! zlib's gzjoin example
+//!
+//! The blocks of a stream go on into those of the next one just fine once
+//! the last block of the first isn't marked final anymore. Blocks don't need
+//! to start on a byte boundary, but each stream ends with padding to one:
+//! streams which end short of it get an empty stored block appended, which
+//! takes up the padding to where the next stream begins. Streams still have
+//! to be decoded to find their last block, but the data is only copied.
+
+use std::cmp;
+use std::io::{self, BufRead, Read, Write};
+
+use super::{Inflater, HISTORY};
+use crate::consume_with;
+
+// The bytes of a stream which have been consumed from a `BufRead`, which
+// are yet to be written out
+struct Tee<'a, B> {
+    inner: B,
+    kept: &'a mut Vec<u8>,
+    count: u64,
+}
+
+impl<'a, B: BufRead> Read for Tee<'a, B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, B: BufRead> BufRead for Tee<'a, B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let kept = &mut *self.kept;
+        consume_with(&mut self.inner, amt, |buf| kept.extend_from_slice(buf));
+        self.count += amt as u64;
+    }
+}
+
+/// Joins complete DEFLATE streams into a single stream, which decodes to
+/// the data of all of them one after the other. The streams mustn't use a
+/// preset dictionary.
+pub struct Joiner<W> {
+    w: W,
+    // the last block of the last stream appended, from the byte it starts
+    // in, with the bit offsets of its start and of the end of the stream
+    tail: Vec<u8>,
+    last: usize,
+    end: usize,
+    inflater: Inflater,
+}
+
+impl<W: Write> Joiner<W> {
+    /// Creates a new joiner which writes the joined stream to `w`
+    pub fn new(w: W) -> Joiner<W> {
+        Joiner {
+            w,
+            tail: Vec::new(),
+            last: 0,
+            end: 0,
+            inflater: Inflater::new(),
+        }
+    }
+
+    /// Appends the stream read from `r`, which is left right behind the
+    /// stream's last byte. Returns the length of the stream's data.
+    pub fn append<R: BufRead>(&mut self, r: R) -> io::Result<u64> {
+        // The stream so far goes on, and has to reach a byte boundary
+        if !self.tail.is_empty() {
+            self.tail[self.last / 8] &= !(1 << (self.last % 8));
+            let used = self.end % 8;
+            if used != 0 {
+                let n = self.tail.len();
+                self.tail[n - 1] &= (1 << used) - 1;
+                if used > 5 {
+                    self.tail.push(0);
+                }
+                self.tail.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+            }
+            self.w.write_all(&self.tail)?;
+            self.tail.clear();
+        }
+
+        // Everything up to the block being decoded can be written out, as
+        // it may only turn out to be the last one at its end
+        let mut kept = Vec::new();
+        let mut src = Tee {
+            inner: r,
+            kept: &mut kept,
+            count: 0,
+        };
+        self.inflater.reset();
+        let mut len = 0;
+        let mut last = 0;
+        while !self.inflater.eof() {
+            if self.inflater.at_boundary() {
+                last = self.inflater.bit_position(src.count);
+                let done = cmp::min(last / 8, src.count);
+                let n = src.kept.len() - (src.count - done) as usize;
+                self.w.write_all(&src.kept[..n])?;
+                src.kept.drain(..n);
+            }
+            len += self.inflater.inflate(&mut src, HISTORY)? as u64;
+        }
+        let start = (src.count - src.kept.len() as u64) * 8;
+        self.last = (last - start) as usize;
+        self.end = (src.count * 8 - start) as usize - self.inflater.padding;
+        self.tail = kept;
+        Ok(len)
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.w
+    }
+
+    /// Finishes the joined stream, returning the wrapped writer. Without any
+    /// streams appended, this writes an empty stream.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = if self.tail.is_empty() {
+            self.w.write_all(&[0x03, 0x00])
+        } else {
+            self.w.write_all(&self.tail)
+        };
+        (self.w, result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Joiner;
+    use flate::{Decoder, Encoder};
+    use std::io::{Read, Write};
+
+    fn encode(data: &[u8], level: u8) -> Vec<u8> {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        e.write_all(data).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    fn join(streams: &[Vec<u8>]) -> Vec<u8> {
+        let mut j = Joiner::new(Vec::new());
+        for stream in streams.iter() {
+            j.append(&stream[..]).unwrap();
+        }
+        let (joined, result) = j.finish();
+        result.unwrap();
+        joined
+    }
+
+    fn decode(stream: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(stream);
+        let mut out = Vec::new();
+        d.read_to_end(&mut out).unwrap();
+        assert!(d.trailing().is_empty());
+        out
+    }
+
+    #[test]
+    fn joins() {
+        let text = &include_bytes!("../data/test.txt")[..];
+        let large = &include_bytes!("../data/test.large")[..200000];
+        let pieces: Vec<&[u8]> = vec![text, b"", b"a", &text[100..], large, b"xyz"];
+        // every level, for streams ending at all sorts of bit offsets
+        for level in 0..10 {
+            let streams: Vec<Vec<u8>> = pieces.iter().map(|p| encode(p, level)).collect();
+            let joined = join(&streams);
+            assert!(decode(&joined) == pieces.concat());
+            let size: usize = streams.iter().map(|s| s.len()).sum();
+            assert!(joined.len() <= size + 5 * streams.len());
+        }
+        assert!(decode(&join(&[])).is_empty());
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut stream = encode(b"hello", 6);
+        stream.extend_from_slice(b"rest");
+        let mut r = &stream[..];
+        let mut j = Joiner::new(Vec::new());
+        assert_eq!(j.append(&mut r).unwrap(), 5);
+        assert_eq!(r, b"rest");
+    }
+}
@@ -0,0 +1,1551 @@
+//! DEFLATE Compression and Decompression. Requires `flate` feature, enabled by default
+//!
+//! This module contains an implementation of the DEFLATE compression scheme.
+//! This format is often used as the underpinning of other compression formats.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::flate;
+//! use std::fs::File;
+//! use std::path::Path;
+//!
+//! let stream = File::open(&Path::new("path/to/file.flate")).unwrap();
+//! let mut decompressed = Vec::new();
+//! flate::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! Compressing works the same way through the `Encoder`:
+//!
+//! ```rust
+//! use compress::flate;
+//! use std::io::{Read, Write};
+//!
+//! let mut e = flate::Encoder::new(Vec::new());
+//! e.write_all(b"some text").unwrap();
+//! let (encoded, result) = e.finish();
+//! result.unwrap();
+//!
+//! let mut decoded = Vec::new();
+//! flate::Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+//! assert_eq!(&decoded[..], b"some text");
+//! ```
+//!
+//! # Related links
+//!
+//! * http://tools.ietf.org/html/rfc1951 - RFC that this implementation is based
+//!   on
+//! * http://www.gzip.org/zlib/rfc-deflate.html - simplified version of RFC 1951
+//!   used as a reference
+//! * http://svn.ghostscript.com/ghostscript/trunk/gs/zlib/contrib/puff/puff.c -
+//!   Much of this code is based on the puff.c implementation found here
+
+use std::cmp;
+use std::io::{self, BufRead, Read};
+use std::vec::Vec;
+
+pub use self::encoder::{Encoder, Token, DEFAULT_LEVEL};
+pub use self::index::{Checkpoint, Index, IndexedDecoder};
+pub use self::join::Joiner;
+pub use self::parallel::{ParallelEncoder, CHUNK_SIZE};
+pub use self::speculative::ParallelDecoder;
+pub use self::trace::{Block, BlockKind, Trace};
+pub use self::websocket::{MessageDecoder, MessageEncoder};
+
+mod encoder;
+mod index;
+mod join;
+mod optimal;
+pub(crate) mod parallel;
+mod speculative;
+mod trace;
+mod websocket;
+
+const MAXBITS: usize = 15;
+const MAXLCODES: u16 = 286;
+// Deflate64 makes use of all 32 distance codes
+const MAXDCODES: u16 = 32;
+const MAXCODES: u16 = MAXLCODES + MAXDCODES;
+const HISTORY: usize = 32 * 1024;
+
+// extra base length for codes 257-285
+static EXTRALENS: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+// extra bits to read for codes 257-285
+static EXTRABITS: [u16; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+// base offset for distance codes.
+static EXTRADIST: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+// number of bits to read for distance codes (to add to the offset)
+static EXTRADBITS: [u16; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// Deflate64 gives length code 285 16 extra bits on top of a length of 3,
+// and extends the distances with codes 30 and 31
+static EXTRALENS64: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 3,
+];
+static EXTRABITS64: [u16; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 16,
+];
+static EXTRADIST64: [u16; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577, 32769, 49153,
+];
+static EXTRADBITS64: [u16; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14,
+];
+// code lengths of the fixed literal/length code
+static FIXED_LIT_LENGTHS: [u8; 288] = {
+    let mut lens = [8; 288];
+    let mut i = 144;
+    while i < 288 {
+        lens[i] = if i < 256 {
+            9
+        } else if i < 280 {
+            7
+        } else {
+            8
+        };
+        i += 1;
+    }
+    lens
+};
+// order in which the code length code lengths are transmitted
+static ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// The window size and length/distance tables of a flavor of DEFLATE
+struct Variant {
+    window: usize,
+    extralens: &'static [u16],
+    extrabits: &'static [u16],
+    extradist: &'static [u16],
+    extradbits: &'static [u16],
+}
+
+static DEFLATE: Variant = Variant {
+    window: HISTORY,
+    extralens: &EXTRALENS,
+    extrabits: &EXTRABITS,
+    extradist: &EXTRADIST,
+    extradbits: &EXTRADBITS,
+};
+
+static DEFLATE64: Variant = Variant {
+    window: 64 * 1024,
+    extralens: &EXTRALENS64,
+    extrabits: &EXTRABITS64,
+    extradist: &EXTRADIST64,
+    extradbits: &EXTRADBITS64,
+};
+
+enum Error {
+    HuffmanTreeTooLarge,
+    InvalidBlockCode,
+    InvalidHuffmanHeaderSymbol,
+    InvalidHuffmanTree,
+    InvalidHuffmanTreeHeader,
+    InvalidHuffmanCode,
+    InvalidStaticSize,
+    NotEnoughBits,
+}
+
+fn error<T>(e: Error) -> io::Result<T> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        match e {
+            Error::HuffmanTreeTooLarge => "huffman tree too large",
+            Error::InvalidBlockCode => "invalid block code",
+            Error::InvalidHuffmanHeaderSymbol => "invalid huffman header symbol",
+            Error::InvalidHuffmanTree => "invalid huffman tree",
+            Error::InvalidHuffmanTreeHeader => "invalid huffman tree header",
+            Error::InvalidHuffmanCode => "invalid huffman code",
+            Error::InvalidStaticSize => "invalid static size",
+            Error::NotEnoughBits => "not enough bits",
+        },
+    ))
+}
+
+// Number of bits resolved by the first lookup into a Huffman table, longer
+// codes continue into a sub-table
+const PRIMARY_BITS: usize = 9;
+// Table entries either hold a symbol and its code length, or point to a
+// sub-table (offset and index width)
+const SUBTABLE: u32 = 1 << 31;
+const INPUT_BUFFER: usize = 8 * 1024;
+
+#[derive(Clone)]
+struct HuffmanTree {
+    /// Lookup table indexed by the next `bits` bits of the stream, followed
+    /// by the sub-tables for codes longer than that. Each entry is either
+    /// `symbol | length << 16`, or `SUBTABLE | bits << 16 | offset` pointing
+    /// to the sub-table which is indexed by the following `bits` bits. A zero
+    /// entry marks a code which isn't part of an incomplete code.
+    table: Vec<u32>,
+    bits: usize,
+}
+
+impl HuffmanTree {
+    /// Constructs a new huffman tree for decoding. If the given array has
+    /// length N, then the huffman tree can be used to decode N symbols. Each
+    /// entry in the array corresponds to the length of the nth symbol.
+    fn construct(lens: &[u16]) -> io::Result<HuffmanTree> {
+        // Collect the lengths of all symbols
+        let mut count = [0u16; MAXBITS + 1];
+        for len in lens.iter() {
+            count[*len as usize] += 1;
+        }
+        let maxlen = (1..(MAXBITS + 1))
+            .rev()
+            .find(|&i| count[i] != 0)
+            .unwrap_or(0);
+        let bits = maxlen.clamp(1, PRIMARY_BITS);
+        let mut tree = HuffmanTree {
+            table: vec![0; 1 << bits],
+            bits,
+        };
+        // If there weren't actually any codes, then we're done
+        if maxlen == 0 {
+            return Ok(tree);
+        }
+
+        // Make sure that this tree is sane. Each bit gives us 2x more codes to
+        // work with, but if the counts add up to greater than the available
+        // amount, then this is an invalid table.
+        let mut left = 1;
+        for &c in count[1..].iter() {
+            left *= 2;
+            left -= c as isize;
+            if left < 0 {
+                return error(Error::InvalidHuffmanTree);
+            }
+        }
+
+        // Generate the first canonical code of each length, and the code of
+        // every symbol, bit reversed as the stream is read LSB first
+        let mut next = [0u32; MAXBITS + 1];
+        let mut code = 0;
+        count[0] = 0;
+        for i in 1..(MAXBITS + 1) {
+            code = (code + count[i - 1] as u32) << 1;
+            next[i] = code;
+        }
+        let mut codes = vec![0u32; lens.len()];
+        for (sym, &len) in lens.iter().enumerate() {
+            if len != 0 {
+                codes[sym] = next[len as usize].reverse_bits() >> (32 - len as usize);
+                next[len as usize] += 1;
+            }
+        }
+
+        // Size the sub-tables after the longest code sharing each prefix
+        let mask = (1 << bits) - 1;
+        let mut sub = vec![0usize; 1 << bits];
+        for (sym, &len) in lens.iter().enumerate() {
+            let len = len as usize;
+            if len > bits {
+                let prefix = codes[sym] as usize & mask;
+                sub[prefix] = cmp::max(sub[prefix], len - bits);
+            }
+        }
+        for (prefix, &width) in sub.iter().enumerate() {
+            if width != 0 {
+                let offset = tree.table.len();
+                tree.table[prefix] = SUBTABLE | (width as u32) << 16 | offset as u32;
+                tree.table.extend((0..(1 << width)).map(|_| 0));
+            }
+        }
+
+        // Fill in every index whose low bits are a symbol's code
+        for (sym, &len) in lens.iter().enumerate() {
+            let len = len as usize;
+            if len == 0 {
+                continue;
+            }
+            let entry = sym as u32 | (len as u32) << 16;
+            let code = codes[sym] as usize;
+            let (offset, width, index) = if len <= bits {
+                (0, bits, code)
+            } else {
+                let prefix = code & mask;
+                (
+                    (tree.table[prefix] & 0xffff) as usize,
+                    sub[prefix],
+                    code >> bits,
+                )
+            };
+            let step = if len <= bits { len } else { len - bits };
+            let mut i = index;
+            while i < 1 << width {
+                tree.table[offset + i] = entry;
+                i += 1 << step;
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Decodes a codepoint from the buffer.
+    ///
+    /// The next `bits` bits of the stream are looked up in the table, which
+    /// yields either the symbol directly or a sub-table to look the following
+    /// bits up in.
+    fn decode<B: BufRead>(&self, s: &mut Inflater, src: &mut B) -> io::Result<u16> {
+        if s.bitcnt < MAXBITS {
+            s.refill(src, 0)?;
+        }
+        let mut entry = self.lookup(s.bitbuf);
+        let mut len = (entry >> 16) as usize;
+        if (len == 0 || len > s.bitcnt) && s.bitcnt < MAXBITS {
+            // The code goes on past the bits which could be peeked at, so the
+            // bits at hand are all part of it
+            s.refill(src, MAXBITS)?;
+            entry = self.lookup(s.bitbuf);
+            len = (entry >> 16) as usize;
+        }
+        if len == 0 {
+            return error(Error::InvalidHuffmanCode);
+        }
+        if len > s.bitcnt {
+            return error(Error::NotEnoughBits);
+        }
+        s.bitbuf >>= len;
+        s.bitcnt -= len;
+        Ok(entry as u16)
+    }
+
+    fn lookup(&self, bitbuf: u64) -> u32 {
+        let entry = self.table[bitbuf as usize & ((1 << self.bits) - 1)];
+        if entry & SUBTABLE == 0 {
+            return entry;
+        }
+        let width = (entry >> 16) & 0x7fff;
+        let index = (bitbuf >> self.bits) as usize & ((1 << width) - 1);
+        self.table[(entry & 0xffff) as usize + index]
+    }
+}
+
+/// Input read ahead from a plain reader in bulk, which lets the decoders
+/// working on a `BufRead` take any reader. What's left of it once a stream
+/// has been decoded is data following the stream.
+pub(crate) struct Input {
+    buf: Vec<u8>,
+    pos: usize,
+    end: usize,
+}
+
+impl Input {
+    pub(crate) fn new() -> Input {
+        Input {
+            buf: vec![0; INPUT_BUFFER],
+            pos: 0,
+            end: 0,
+        }
+    }
+
+    /// Buffered reader over `r` filling this input
+    pub(crate) fn source<'a, R: Read>(&'a mut self, r: &'a mut R) -> Source<'a, R> {
+        Source { r, input: self }
+    }
+
+    /// The bytes read from the reader but not consumed yet
+    pub(crate) fn remaining(&self) -> &[u8] {
+        &self.buf[self.pos..self.end]
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pos = 0;
+        self.end = 0;
+    }
+}
+
+pub(crate) struct Source<'a, R: 'a> {
+    r: &'a mut R,
+    input: &'a mut Input,
+}
+
+impl<'a, R: Read> Read for Source<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.pos == self.input.end && buf.len() >= self.input.buf.len() {
+            return self.r.read(buf);
+        }
+        let n = {
+            let avail = self.fill_buf()?;
+            let n = cmp::min(avail.len(), buf.len());
+            buf[..n].copy_from_slice(&avail[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, R: Read> BufRead for Source<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let input = &mut *self.input;
+        if input.pos == input.end {
+            input.end = self.r.read(&mut input.buf)?;
+            input.pos = 0;
+        }
+        Ok(&input.buf[input.pos..input.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.input.pos = cmp::min(self.input.pos + amt, self.input.end);
+    }
+}
+
+// Counts the bytes consumed from a source
+struct Counted<B> {
+    inner: B,
+    count: u64,
+}
+
+impl<B: BufRead> Read for Counted<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl<B: BufRead> BufRead for Counted<B> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// Where decoding stands, between calls to `Inflater::inflate`
+enum State {
+    /// At the start of a block
+    Header,
+    /// Within a stored block, with this many bytes left
+    Stored(usize),
+    /// Within a compressed block, at the next symbol
+    Codes,
+    /// Within a compressed block, with a copy of `len` bytes from `dist`
+    /// back left to do
+    Copy(usize, usize),
+    /// Past the end of the final block
+    Done,
+}
+
+/// The state of decoding a DEFLATE stream, which is read from whatever
+/// `BufRead` is handed in. Only the bytes of the stream get consumed from it.
+///
+/// Decoded data goes straight into the window of history kept for back
+/// references, and no more is decoded at a time than the caller asks for,
+/// so memory use doesn't depend on the size of the blocks in the stream.
+pub(crate) struct Inflater {
+    variant: &'static Variant,
+    // the most recent output as a ring buffer, written at `wpos`, the last
+    // `have` bytes of which are valid
+    window: Vec<u8>,
+    wpos: usize,
+    have: usize,
+    // farthest back a copy may reach, which is less than the window for
+    // streams declaring a smaller one
+    limit: usize,
+
+    state: State,
+    // whether the current block is the final one
+    last: bool,
+    trees: Option<(HuffmanTree, HuffmanTree)>,
+
+    bitbuf: u64,
+    bitcnt: usize,
+    // bytes loaded into the bit buffer which are still to be consumed from
+    // the source
+    peeked: usize,
+    // bits of the last byte of the stream left over after the final block
+    padding: usize,
+
+    fixed: Option<(HuffmanTree, HuffmanTree)>,
+    // preset history each stream starts out with
+    dictionary: Vec<u8>,
+    // what there is to know about the current block, when tracing
+    trace: Option<Block>,
+}
+
+impl Inflater {
+    pub(crate) fn new() -> Inflater {
+        Inflater {
+            variant: &DEFLATE,
+            window: vec![0; HISTORY],
+            wpos: 0,
+            have: 0,
+            limit: HISTORY,
+            state: State::Header,
+            last: false,
+            trees: None,
+            bitbuf: 0,
+            bitcnt: 0,
+            peeked: 0,
+            padding: 0,
+            fixed: None,
+            dictionary: Vec::new(),
+            trace: None,
+        }
+    }
+
+    fn deflate64(&mut self) {
+        self.variant = &DEFLATE64;
+        self.window.resize(DEFLATE64.window, 0);
+        self.limit = DEFLATE64.window;
+    }
+
+    /// Rejects copies from further back than `size` bytes
+    pub(crate) fn limit_window(&mut self, size: usize) {
+        self.limit = cmp::min(size, self.variant.window);
+    }
+
+    /// Primes the history with the last 32K of `dict`, now and whenever the
+    /// decoder is reset
+    pub(crate) fn set_dictionary(&mut self, dict: &[u8]) {
+        self.dictionary.clear();
+        self.dictionary
+            .extend_from_slice(&dict[dict.len().saturating_sub(self.variant.window)..]);
+        self.restore_dictionary();
+    }
+
+    fn restore_dictionary(&mut self) {
+        let n = self.dictionary.len();
+        self.window[..n].copy_from_slice(&self.dictionary);
+        self.wpos = n % self.variant.window;
+        self.have = n;
+    }
+
+    // The history as it stands, oldest byte first
+    fn history(&self) -> Vec<u8> {
+        let start = (self.wpos + self.variant.window - self.have) % self.variant.window;
+        let mut history = Vec::with_capacity(self.have);
+        if start + self.have <= self.variant.window {
+            history.extend_from_slice(&self.window[start..start + self.have]);
+        } else {
+            history.extend_from_slice(&self.window[start..]);
+            history.extend_from_slice(&self.window[..self.wpos]);
+        }
+        history
+    }
+
+    // Number of bits of the stream used up, given the number of bytes
+    // consumed from the source so far
+    fn bit_position(&self, consumed: u64) -> u64 {
+        (consumed + self.peeked as u64) * 8 - self.bitcnt as u64
+    }
+
+    // Resumes decoding in the middle of a byte, of which the `cnt` bits in
+    // `bits` are yet to be used
+    fn prime(&mut self, bits: u8, cnt: usize) {
+        self.bitbuf = bits as u64;
+        self.bitcnt = cnt;
+    }
+
+    // Whether decoding is at the start of a block, or done with the stream
+    fn at_boundary(&self) -> bool {
+        matches!(self.state, State::Header | State::Done)
+    }
+
+    /// Decodes up to `limit` bytes of output into the window, which has to be
+    /// no more than the window size, and returns how many were decoded. Only
+    /// a single step is taken: this may stop short at the end of a block, and
+    /// return 0 for a block header or an empty block.
+    fn inflate<B: BufRead>(&mut self, src: &mut B, limit: usize) -> io::Result<usize> {
+        match self.state {
+            State::Header => {
+                self.header(src)?;
+                Ok(0)
+            }
+            State::Stored(left) => self.stored(src, left, limit),
+            State::Codes | State::Copy(..) => {
+                let (lens, dist) = self.trees.take().unwrap();
+                let result = self.codes(src, &lens, &dist, limit);
+                self.trees = Some((lens, dist));
+                result
+            }
+            State::Done => Ok(0),
+        }
+    }
+
+    fn header<B: BufRead>(&mut self, src: &mut B) -> io::Result<()> {
+        self.last = self.bits(src, 1)? == 1;
+        if let Some(ref mut block) = self.trace {
+            block.last = self.last;
+        }
+        match self.bits(src, 2)? {
+            0 => {
+                self.settle(src);
+                let mut header = [0; 4];
+                src.read_exact(&mut header)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if !nlen != len {
+                    return error(Error::InvalidStaticSize);
+                }
+                self.state = State::Stored(len as usize);
+                if let Some(ref mut block) = self.trace {
+                    block.kind = BlockKind::Stored;
+                }
+            }
+            1 => {
+                let trees = match self.fixed {
+                    Some(ref trees) => trees.clone(),
+                    None => {
+                        let lens: Vec<u16> = FIXED_LIT_LENGTHS.iter().map(|&l| l as u16).collect();
+                        let trees = (
+                            HuffmanTree::construct(&lens)?,
+                            HuffmanTree::construct(&[5; 32])?,
+                        );
+                        self.fixed = Some(trees.clone());
+                        trees
+                    }
+                };
+                self.trees = Some(trees);
+                self.state = State::Codes;
+                if let Some(ref mut block) = self.trace {
+                    block.kind = BlockKind::Fixed;
+                    block.lit_lengths = FIXED_LIT_LENGTHS.to_vec();
+                    block.dist_lengths = vec![5; 30];
+                }
+            }
+            2 => {
+                self.trees = Some(self.dynamic(src)?);
+                self.state = State::Codes;
+            }
+            _ => return error(Error::InvalidBlockCode),
+        }
+        Ok(())
+    }
+
+    fn end_block<B: BufRead>(&mut self, src: &mut B) {
+        if self.last {
+            self.padding = self.bitcnt % 8;
+            self.settle(src);
+            self.state = State::Done;
+        } else {
+            self.state = State::Header;
+        }
+    }
+
+    fn stored<B: BufRead>(&mut self, src: &mut B, left: usize, limit: usize) -> io::Result<usize> {
+        let want = cmp::min(left, limit);
+        let mut n = 0;
+        while n < want {
+            let avail = src.fill_buf()?;
+            if avail.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stored block cut short",
+                ));
+            }
+            let len = cmp::min(
+                cmp::min(avail.len(), want - n),
+                self.window.len() - self.wpos,
+            );
+            self.window[self.wpos..self.wpos + len].copy_from_slice(&avail[..len]);
+            src.consume(len);
+            self.advance(len);
+            n += len;
+        }
+        if n == left {
+            self.end_block(src);
+        } else {
+            self.state = State::Stored(left - n);
+        }
+        Ok(n)
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.wpos = (self.wpos + n) % self.variant.window;
+        self.have = cmp::min(self.have + n, self.variant.window);
+    }
+
+    // Appends `len` bytes starting `dist` bytes back to the window
+    fn copy(&mut self, dist: usize, len: usize) {
+        let size = self.variant.window;
+        let from = (self.wpos + size - dist) % size;
+        if dist >= len && from + len <= size && self.wpos + len <= size {
+            self.window.copy_within(from..from + len, self.wpos);
+        } else {
+            let (mut from, mut to) = (from, self.wpos);
+            for _ in 0..len {
+                self.window[to] = self.window[from];
+                from = (from + 1) % size;
+                to = (to + 1) % size;
+            }
+        }
+        self.advance(len);
+    }
+
+    // Tops the bit buffer up with the input available from `src`. Bytes are
+    // only consumed from `src` once all of their bits have been used, the
+    // ones behind them are just peeked at, so that exactly the bytes of the
+    // stream end up consumed. Getting past the peeked bytes means consuming
+    // them first, which is done only if fewer than `need` bits are buffered,
+    // when they are all about to be used. Hitting the end of the input isn't
+    // an error here, only running out of bits is.
+    fn refill<B: BufRead>(&mut self, src: &mut B, need: usize) -> io::Result<()> {
+        let used = self.peeked.saturating_sub(self.bitcnt.div_ceil(8));
+        src.consume(used);
+        self.peeked -= used;
+        loop {
+            let input = src.fill_buf()?;
+            let avail = input.get(self.peeked..).unwrap_or(&[]);
+            if avail.len() >= 8 {
+                let mut word = [0; 8];
+                word.copy_from_slice(&avail[..8]);
+                self.bitbuf |= u64::from_le_bytes(word) << self.bitcnt;
+                let n = (63 - self.bitcnt) / 8;
+                self.peeked += n;
+                self.bitcnt += n * 8;
+                return Ok(());
+            }
+            for &byte in avail.iter() {
+                if self.bitcnt > 56 {
+                    break;
+                }
+                self.bitbuf |= (byte as u64) << self.bitcnt;
+                self.peeked += 1;
+                self.bitcnt += 8;
+            }
+            if self.bitcnt > 56 || self.bitcnt >= need || self.peeked == 0 {
+                return Ok(());
+            }
+            src.consume(self.peeked);
+            self.peeked = 0;
+        }
+    }
+
+    // Bytes in the stream are LSB first, so the bitbuf is appended to from the
+    // left and consumed from the right.
+    fn bits<B: BufRead>(&mut self, src: &mut B, cnt: usize) -> io::Result<u16> {
+        if self.bitcnt < cnt {
+            self.refill(src, cnt)?;
+            if self.bitcnt < cnt {
+                return error(Error::NotEnoughBits);
+            }
+        }
+        let ret = self.bitbuf & ((1 << cnt) - 1);
+        self.bitbuf >>= cnt;
+        self.bitcnt -= cnt;
+        Ok(ret as u16)
+    }
+
+    // Drops the bits up to the next byte boundary, and consumes the bytes
+    // used up so far from `src`, leaving it right where decoding has got to
+    fn settle<B: BufRead>(&mut self, src: &mut B) {
+        let n = self.bitcnt % 8;
+        self.bitcnt -= n;
+        src.consume(self.peeked.saturating_sub(self.bitcnt / 8));
+        self.bitbuf = 0;
+        self.bitcnt = 0;
+        self.peeked = 0;
+    }
+
+    fn codes<B: BufRead>(
+        &mut self,
+        src: &mut B,
+        lens: &HuffmanTree,
+        dist: &HuffmanTree,
+        limit: usize,
+    ) -> io::Result<usize> {
+        let mut n = 0;
+        loop {
+            if let State::Copy(len, dist) = self.state {
+                let amt = cmp::min(len, limit - n);
+                self.copy(dist, amt);
+                n += amt;
+                if amt < len {
+                    self.state = State::Copy(len - amt, dist);
+                    return Ok(n);
+                }
+                self.state = State::Codes;
+            }
+            if n == limit {
+                return Ok(n);
+            }
+            let sym = lens.decode(self, src)?;
+            match sym {
+                n if n < 256 => {
+                    self.window[self.wpos] = sym as u8;
+                    self.advance(1);
+                    if let Some(ref mut block) = self.trace {
+                        block.tokens.push(Token::Literal(sym as u8));
+                    }
+                }
+                256 => {
+                    self.end_block(src);
+                    return Ok(n);
+                }
+                n => {
+                    // figure out len/dist that we're working with
+                    let v = self.variant;
+                    let n = (n - 257) as usize;
+                    if n >= v.extralens.len() {
+                        return error(Error::InvalidHuffmanCode);
+                    }
+                    let len =
+                        v.extralens[n] as usize + self.bits(src, v.extrabits[n] as usize)? as usize;
+
+                    let dist = dist.decode(self, src)? as usize;
+                    if dist >= v.extradist.len() {
+                        return error(Error::InvalidHuffmanCode);
+                    }
+                    let dist = v.extradist[dist] as usize
+                        + self.bits(src, v.extradbits[dist] as usize)? as usize;
+
+                    if dist > self.have || dist > self.limit {
+                        return error(Error::InvalidHuffmanCode);
+                    }
+                    self.state = State::Copy(len, dist);
+                    if let Some(ref mut block) = self.trace {
+                        // Deflate64 copies may be too long for a token
+                        let mut left = len;
+                        while left > 0 {
+                            let part = cmp::min(left, u16::MAX as usize);
+                            block.tokens.push(Token::Match(part as u16, dist as u16));
+                            left -= part;
+                        }
+                    }
+                    continue;
+                }
+            }
+            n += 1;
+        }
+    }
+
+    fn dynamic<B: BufRead>(&mut self, src: &mut B) -> io::Result<(HuffmanTree, HuffmanTree)> {
+        let hlit = self.bits(src, 5)? + 257; // number of length codes
+        let hdist = self.bits(src, 5)? + 1; // number of distance codes
+        let hclen = self.bits(src, 4)? + 4; // number of code length codes
+        if hlit > MAXLCODES || hdist as usize > self.variant.extradist.len() {
+            return error(Error::HuffmanTreeTooLarge);
+        }
+
+        // Read off the code length codes, and then build the huffman tree which
+        // is then used to decode the actual huffman tree for the rest of the
+        // data.
+        let mut lengths = [0; 19];
+        for i in 0..(hclen as usize) {
+            lengths[ORDER[i]] = self.bits(src, 3)?;
+        }
+        let tree = HuffmanTree::construct(&lengths)?;
+
+        // Decode all of the length and distance codes in one go, we'll
+        // partition them into two huffman trees later
+        let mut lengths = [0; MAXCODES as usize];
+        let mut i = 0;
+        while i < hlit + hdist {
+            let symbol = tree.decode(self, src)?;
+            match symbol {
+                n if n < 16 => {
+                    lengths[i as usize] = symbol;
+                    i += 1;
+                }
+                16 if i == 0 => return error(Error::InvalidHuffmanHeaderSymbol),
+                16 => {
+                    let prev = lengths[i as usize - 1];
+                    for _ in 0..(self.bits(src, 2)? + 3) {
+                        if i >= hlit + hdist {
+                            return error(Error::InvalidHuffmanTreeHeader);
+                        }
+                        lengths[i as usize] = prev;
+                        i += 1;
+                    }
+                }
+                // all codes start out as 0, so these just skip
+                17 => {
+                    i += self.bits(src, 3)? + 3;
+                }
+                18 => {
+                    i += self.bits(src, 7)? + 11;
+                }
+                _ => return error(Error::InvalidHuffmanHeaderSymbol),
+            }
+        }
+        if i > hlit + hdist {
+            return error(Error::InvalidHuffmanTreeHeader);
+        }
+
+        // Use the decoded codes to construct yet another huffman tree
+        let arr = &lengths[..(hlit as usize)];
+        let lencode = HuffmanTree::construct(arr)?;
+        let arr = &lengths[(hlit as usize)..((hlit + hdist) as usize)];
+        let distcode = HuffmanTree::construct(arr)?;
+        if let Some(ref mut block) = self.trace {
+            block.kind = BlockKind::Dynamic;
+            block.lit_lengths = lengths[..(hlit as usize)]
+                .iter()
+                .map(|&l| l as u8)
+                .collect();
+            block.dist_lengths = arr.iter().map(|&l| l as u8).collect();
+        }
+        Ok((lencode, distcode))
+    }
+
+    /// Decompresses into `buf`, returning 0 only at the end of the stream.
+    /// `src` is left right behind the stream's last byte by then.
+    pub(crate) fn read<B: BufRead>(&mut self, src: &mut B, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let limit = cmp::min(buf.len(), self.variant.window);
+        loop {
+            let n = self.inflate(src, limit)?;
+            if n > 0 {
+                self.output(&mut buf[..n]);
+                return Ok(n);
+            }
+            if let State::Done = self.state {
+                return Ok(0);
+            }
+        }
+    }
+
+    // Copies out the last output, as much of it as fits `buf`, which is the
+    // end of the window
+    pub(crate) fn output(&self, buf: &mut [u8]) {
+        let n = buf.len();
+        let start = (self.wpos + self.variant.window - n) % self.variant.window;
+        let first = cmp::min(n, self.variant.window - start);
+        buf[..first].copy_from_slice(&self.window[start..start + first]);
+        buf[first..].copy_from_slice(&self.window[..n - first]);
+    }
+
+    pub(crate) fn eof(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.restore_dictionary();
+        self.state = State::Header;
+        self.last = false;
+        self.trees = None;
+        self.bitbuf = 0;
+        self.bitcnt = 0;
+        self.peeked = 0;
+    }
+}
+
+/// The structure that is used to decode a DEFLATE data stream. This wraps an
+/// internal reader which is used as the source of all data.
+pub struct Decoder<R> {
+    /// Wrapped reader which is exposed to allow getting it back. Note that
+    /// the decoder reads ahead of what it has decoded so far, the data read
+    /// past the end of the stream is available through `trailing`.
+    pub r: R,
+
+    input: Input,
+    inflater: Inflater,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new flate decoder which will read data from the specified
+    /// source
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            input: Input::new(),
+            inflater: Inflater::new(),
+        }
+    }
+
+    /// Creates a new decoder of Deflate64, the variant of DEFLATE with a 64K
+    /// window found in zip archives as method 9
+    pub fn deflate64(r: R) -> Decoder<R> {
+        let mut d = Decoder::new(r);
+        d.inflater.deflate64();
+        d
+    }
+
+    /// Creates a new flate decoder for a stream which was compressed with the
+    /// given preset dictionary, see `Encoder::with_dictionary`
+    pub fn with_dictionary(r: R, dict: &[u8]) -> Decoder<R> {
+        let mut d = Decoder::new(r);
+        d.inflater.set_dictionary(dict);
+        d
+    }
+
+    /// Returns whether this deflate stream has reached the EOF marker
+    pub fn eof(&self) -> bool {
+        self.inflater.eof()
+    }
+
+    /// Turns this decoder into an iterator over the blocks of the stream,
+    /// telling how each of them is coded instead of decoding the data. The
+    /// decoder shouldn't have read anything yet.
+    pub fn trace(self) -> Trace<R> {
+        Trace::new(self)
+    }
+
+    /// Returns the bytes which were read from the wrapped reader but follow
+    /// the end of the DEFLATE stream, once `eof` is reached. The rest of the
+    /// data after the stream is still to be read from `r`.
+    pub fn trailing(&self) -> &[u8] {
+        self.input.remaining()
+    }
+
+    /// Resets this flate decoder. Note that this could corrupt an in-progress
+    /// decoding of a stream.
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.inflater.reset();
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut src = self.input.source(&mut self.r);
+        self.inflater.read(&mut src, buf)
+    }
+}
+
+/// Decodes a DEFLATE stream from a buffered reader, consuming exactly the
+/// bytes of the stream from it. Once the end of the stream is reached, the
+/// reader is positioned right behind it, e.g. at whatever trailer a container
+/// format appends, or at the start of another stream.
+pub struct BufDecoder<R> {
+    /// Wrapped reader which is exposed to allow getting it back
+    pub r: R,
+
+    inflater: Inflater,
+}
+
+impl<R: BufRead> BufDecoder<R> {
+    /// Creates a new flate decoder which will read data from the specified
+    /// source
+    pub fn new(r: R) -> BufDecoder<R> {
+        BufDecoder {
+            r,
+            inflater: Inflater::new(),
+        }
+    }
+
+    /// Creates a new decoder of Deflate64 streams, see `Decoder::deflate64`
+    pub fn deflate64(r: R) -> BufDecoder<R> {
+        let mut d = BufDecoder::new(r);
+        d.inflater.deflate64();
+        d
+    }
+
+    /// Creates a new flate decoder for streams which were compressed with the
+    /// given preset dictionary, see `Encoder::with_dictionary`
+    pub fn with_dictionary(r: R, dict: &[u8]) -> BufDecoder<R> {
+        let mut d = BufDecoder::new(r);
+        d.inflater.set_dictionary(dict);
+        d
+    }
+
+    /// Returns whether this deflate stream has reached the EOF marker
+    pub fn eof(&self) -> bool {
+        self.inflater.eof()
+    }
+
+    /// Resets this flate decoder to decode the next stream from the reader.
+    /// Note that this could corrupt an in-progress decoding of a stream.
+    pub fn reset(&mut self) {
+        self.inflater.reset();
+    }
+}
+
+impl<R: BufRead> Read for BufDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inflater.read(&mut self.r, buf)
+    }
+}
+
+#[cfg(test)]
+#[allow(warnings)]
+mod test {
+    use super::super::byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+    use super::super::rand::random;
+    use super::encoder::{self, BitWriter};
+    use super::{BufDecoder, Decoder, Encoder};
+    use std::io::{BufReader, BufWriter, Read, Write};
+    use std::str;
+    #[cfg(feature = "unstable")]
+    use test;
+
+    // The input data for these tests were all generated from the zpipe.c
+    // program found at http://www.zlib.net/zpipe.c and the zlib format has an
+    // extra 2 bytes of header with an 4-byte checksum at the end.
+    fn fixup<'a>(s: &'a [u8]) -> &'a [u8] {
+        &s[2..(s.len() - 4)]
+    }
+
+    fn test_decode(input: &[u8], output: &[u8]) {
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(output.len(), buf.len());
+        let i = buf.iter().zip(output.iter()).position(|(a, b)| a != b);
+        assert!(buf == output);
+    }
+
+    fn test_decode_pure(input: &[u8], output: &[u8]) {
+        let mut d = Decoder::new(BufReader::new(input));
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+
+        assert_eq!(output.len(), buf.len());
+        let i = buf.iter().zip(output.iter()).position(|(a, b)| a != b);
+        assert!(buf == output);
+    }
+
+    #[test]
+    fn decode() {
+        let reference = include_bytes!("../data/test.txt");
+        test_decode(include_bytes!("../data/test.z.0"), reference);
+        test_decode(include_bytes!("../data/test.z.1"), reference);
+        test_decode(include_bytes!("../data/test.z.2"), reference);
+        test_decode(include_bytes!("../data/test.z.3"), reference);
+        test_decode(include_bytes!("../data/test.z.4"), reference);
+        test_decode(include_bytes!("../data/test.z.5"), reference);
+        test_decode(include_bytes!("../data/test.z.6"), reference);
+        test_decode(include_bytes!("../data/test.z.7"), reference);
+        test_decode(include_bytes!("../data/test.z.8"), reference);
+        test_decode(include_bytes!("../data/test.z.9"), reference);
+        test_decode_pure(include_bytes!("../data/test.z.go"), reference);
+    }
+
+    #[test]
+    fn large() {
+        let reference = include_bytes!("../data/test.large");
+        test_decode(include_bytes!("../data/test.large.z.5"), reference);
+    }
+
+    #[test]
+    fn truncated() {
+        let input = fixup(include_bytes!("../data/test.z.9"));
+        for &len in [0, 1, 10, input.len() / 2, input.len() - 1].iter() {
+            let mut d = Decoder::new(&input[..len]);
+            let mut buf = Vec::new();
+            assert!(d.read_to_end(&mut buf).is_err());
+        }
+    }
+
+    #[test]
+    fn long_codes() {
+        // skewed frequencies give codes too long for the primary table
+        let input: Vec<u8> = (0..100000)
+            .map(|_| (random::<u32>() | 1 << 24).trailing_zeros() as u8)
+            .collect();
+        roundtrip(&input);
+        let mut e = Encoder::with_level(Vec::new(), 0);
+        e.write_all(&input[..70000]).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(&decoded[..] == &input[..70000]);
+    }
+
+    #[test]
+    fn one_byte_at_a_time() {
+        let input = include_bytes!("../data/test.z.1");
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        assert!(!d.eof());
+        let mut out = Vec::new();
+        loop {
+            match d.read_u8() {
+                Ok(b) => out.push(b),
+                Err(..) => break,
+            }
+        }
+
+        assert!(d.eof());
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+    }
+
+    #[test]
+    fn random_byte_lengths() {
+        let input = include_bytes!("../data/test.z.1");
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        let mut out = Vec::new();
+        let mut buf = [0u8; 40];
+        loop {
+            match d.read(&mut buf[..(1 + random::<usize>() % 40)]) {
+                Err(..) | Ok(0) => break,
+                Ok(n) => {
+                    out.extend(buf[..n].iter().map(|b| *b));
+                }
+            }
+        }
+        assert!(&out[..] == &include_bytes!("../data/test.txt")[..]);
+    }
+
+    fn roundtrip(bytes: &[u8]) {
+        let mut e = Encoder::new(BufWriter::new(Vec::new()));
+        e.write_all(bytes).unwrap();
+        let (e, err) = e.finish();
+        err.unwrap();
+        let encoded = e.into_inner().unwrap();
+
+        let mut d = Decoder::new(BufReader::new(&encoded[..]));
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], bytes);
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"test");
+        roundtrip(b"");
+        roundtrip(include_bytes!("../data/test.txt"));
+        roundtrip(include_bytes!("../data/test.large"));
+    }
+
+    #[test]
+    fn levels() {
+        let input = include_bytes!("../data/test.txt");
+        let mut sizes = Vec::new();
+        for level in 0..10 {
+            let mut e = Encoder::with_level(Vec::new(), level);
+            e.write_all(input).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            let mut decoded = Vec::new();
+            Decoder::new(&encoded[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert!(&decoded[..] == &input[..]);
+            sizes.push(encoded.len());
+        }
+        // stored blocks only add their 5 byte headers
+        assert_eq!(sizes[0], input.len() + 5);
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[9] <= sizes[1]);
+        assert!(sizes[9] <= sizes[4]);
+    }
+
+    #[test]
+    fn optimal() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::with_level(Vec::new(), 9);
+        e.write_all(input).unwrap();
+        let (best_level, err) = e.finish();
+        err.unwrap();
+
+        let mut e = Encoder::optimal(Vec::new(), 5);
+        e.write_all(input).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        assert!(encoded.len() <= best_level.len());
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(&decoded[..] == &input[..]);
+
+        roundtrip_optimal(b"");
+        roundtrip_optimal(&[7; 1000][..]);
+        let mixed: Vec<u8> = (0..20000u32)
+            .map(|i| if i < 10000 { (i % 7) as u8 } else { random() })
+            .collect();
+        roundtrip_optimal(&mixed);
+    }
+
+    fn roundtrip_optimal(bytes: &[u8]) {
+        let mut e = Encoder::optimal(Vec::new(), 2);
+        e.write_all(bytes).unwrap();
+        e.flush().unwrap();
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded.len(), 2 * bytes.len());
+        assert!(&decoded[..bytes.len()] == bytes && &decoded[bytes.len()..] == bytes);
+    }
+
+    #[test]
+    fn levels_large() {
+        let input = &include_bytes!("../data/test.large")[..500000];
+        for &level in [0, 1, 3, 4, 9].iter() {
+            let mut e = Encoder::with_level(Vec::new(), level);
+            e.write_all(input).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            let mut decoded = Vec::new();
+            Decoder::new(&encoded[..])
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert!(&decoded[..] == &input[..]);
+        }
+    }
+
+    #[test]
+    fn roundtrip_incompressible() {
+        let bytes: Vec<u8> = (0..200000).map(|_| random::<u8>()).collect();
+        roundtrip(&bytes);
+    }
+
+    #[test]
+    fn roundtrip_runs() {
+        let bytes: Vec<u8> = (0..300000).map(|i| (i / 1000) as u8).collect();
+        roundtrip(&bytes);
+        roundtrip(&[b'a'; 100000][..]);
+    }
+
+    #[test]
+    fn sync_flush() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(&input[..1000]).unwrap();
+        e.flush().unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        // the sync flush leaves an empty stored block behind it
+        let mut marker = encoded.windows(4);
+        assert!(marker.any(|w| w == [0, 0, 0xff, 0xff]));
+
+        let mut e = Encoder::new(Vec::new());
+        for chunk in input.chunks(777) {
+            e.write_all(chunk).unwrap();
+            e.flush().unwrap();
+        }
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(&decoded[..] == &input[..]);
+    }
+
+    fn encode(bytes: &[u8], level: u8) -> Vec<u8> {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        encoded
+    }
+
+    #[test]
+    fn exact_consumption() {
+        let input = include_bytes!("../data/test.txt");
+        let parts: [&[u8]; 4] = [&input[..], b"", b"x", &input[..3000]];
+        let mut data = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            data.extend(encode(part, [0, 1, 6, 9][i]));
+        }
+        data.extend(b"trailer");
+
+        for &cap in [1, 3, 7, 64, 8192].iter() {
+            let mut r = BufReader::with_capacity(cap, &data[..]);
+            for part in parts.iter() {
+                let mut d = BufDecoder::new(&mut r);
+                let mut decoded = Vec::new();
+                d.read_to_end(&mut decoded).unwrap();
+                assert!(d.eof());
+                assert!(&decoded[..] == *part);
+            }
+            let mut rest = Vec::new();
+            r.read_to_end(&mut rest).unwrap();
+            assert_eq!(&rest[..], b"trailer");
+        }
+    }
+
+    #[test]
+    fn trailing() {
+        let input = include_bytes!("../data/test.txt");
+        let mut data = encode(input, 6);
+        data.extend(b"trailer");
+        let mut d = Decoder::new(&data[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(&decoded[..] == &input[..]);
+        assert_eq!(d.trailing(), b"trailer");
+
+        // the decoder can carry on with another stream
+        let mut data = encode(b"first", 1);
+        data.extend(encode(b"second", 1));
+        let mut d = BufDecoder::new(&data[..]);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        d.reset();
+        d.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded[..], b"firstsecond");
+        assert!(d.r.is_empty());
+    }
+
+    #[test]
+    fn dictionary() {
+        let dict = br#"{"id": 0, "name": "", "tags": ["alpha", "beta"], "active": true}"#;
+        let msg = br#"{"id": 42, "name": "widget", "tags": ["beta"], "active": false}"#;
+        for &level in [0, 1, 6, 9].iter() {
+            let mut e = Encoder::with_dictionary(Vec::new(), level, dict);
+            e.write_all(msg).unwrap();
+            let (encoded, err) = e.finish();
+            err.unwrap();
+            if level > 0 {
+                assert!(encoded.len() < encode(msg, level).len());
+            }
+
+            let mut decoded = Vec::new();
+            Decoder::with_dictionary(&encoded[..], dict)
+                .read_to_end(&mut decoded)
+                .unwrap();
+            assert_eq!(&decoded[..], &msg[..]);
+            let mut decoded = Vec::new();
+            let mut d = BufDecoder::with_dictionary(&encoded[..], dict);
+            d.read_to_end(&mut decoded).unwrap();
+            assert_eq!(&decoded[..], &msg[..]);
+            if level > 0 {
+                // the stream refers to data before its start without it
+                let mut decoded = Vec::new();
+                assert!(Decoder::new(&encoded[..])
+                    .read_to_end(&mut decoded)
+                    .is_err());
+            }
+        }
+
+        // only the end of a long dictionary is used
+        let input = include_bytes!("../data/test.large");
+        let (dict, input) = (&input[..100000], &input[100000..150000]);
+        let mut e = Encoder::with_dictionary(Vec::new(), 6, dict);
+        e.write_all(input).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::with_dictionary(&encoded[..], dict)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(&decoded[..] == input);
+    }
+
+    // The codes of the fixed literal/length Huffman code
+    fn fixed_codes() -> [u16; 288] {
+        let mut codes = [0; 288];
+        encoder::build_codes(&super::FIXED_LIT_LENGTHS, &mut codes);
+        codes
+    }
+
+    #[test]
+    fn huge_block() {
+        // A single block expanding to megabytes, runs of 258 bytes at
+        // distance 1, comes out in pieces no larger than asked for
+        let codes = fixed_codes();
+        let mut bits = BitWriter::new();
+        bits.bits(1, 1);
+        bits.bits(1, 2);
+        bits.bits(codes[b'a' as usize] as u32, 8);
+        for _ in 0..20000 {
+            bits.bits(codes[285] as u32, 8);
+            bits.bits(0, 5);
+        }
+        bits.bits(codes[256] as u32, 7);
+        bits.align();
+
+        let mut d = Decoder::new(&bits.out[..]);
+        let mut buf = [0; 1000];
+        let mut total = 0;
+        loop {
+            match d.read(&mut buf[..777]).unwrap() {
+                0 => break,
+                n => {
+                    assert!(n <= 777);
+                    assert!(buf[..n].iter().all(|&b| b == b'a'));
+                    total += n;
+                }
+            }
+            assert_eq!(d.inflater.window.len(), super::HISTORY);
+        }
+        assert_eq!(total, 1 + 20000 * 258);
+        assert!(d.eof());
+    }
+
+    #[test]
+    fn deflate64() {
+        // A stored block, then a fixed block copying with a length and the
+        // distance codes only Deflate64 has
+        let data: Vec<u8> = (0..40000).map(|_| random::<u8>()).collect();
+        let mut bits = BitWriter::new();
+        encoder::write_stored(&mut bits, &data, false);
+        let codes = fixed_codes();
+        let dcode = |d: u32| (d.reverse_bits() >> 27, 5);
+        bits.bits(1, 1);
+        bits.bits(1, 2);
+        for &(extra, dc, dextra) in [(65535, 30, 40000 - 32769), (0, 31, 60000 - 49153)].iter() {
+            bits.bits(codes[285] as u32, 8);
+            bits.bits(extra, 16);
+            let (code, len) = dcode(dc);
+            bits.bits(code, len);
+            bits.bits(dextra, 14);
+        }
+        bits.bits(codes[256] as u32, 7);
+        bits.align();
+
+        let mut expected = data.clone();
+        for &(len, dist) in [(65538, 40000), (3, 60000)].iter() {
+            for _ in 0..len {
+                let b = expected[expected.len() - dist];
+                expected.push(b);
+            }
+        }
+        let mut decoded = Vec::new();
+        Decoder::deflate64(&bits.out[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded == expected);
+        let mut decoded = Vec::new();
+        assert!(Decoder::new(&bits.out[..])
+            .read_to_end(&mut decoded)
+            .is_err());
+
+        // streams not making use of the extensions are the same
+        let input = include_bytes!("../data/test.txt");
+        let mut decoded = Vec::new();
+        Decoder::deflate64(&encode(input, 6)[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(&decoded[..] == &input[..]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[bench]
+    fn compress_speed(bh: &mut test::Bencher) {
+        let input = include_bytes!("../data/test.large");
+        bh.iter(|| {
+            let mut e = Encoder::new(Vec::new());
+            e.write_all(input).unwrap();
+            e.finish().1.unwrap();
+        });
+        bh.bytes = input.len() as u64;
+    }
+
+    #[cfg(feature = "unstable")]
+    #[bench]
+    fn decompress_speed(bh: &mut test::Bencher) {
+        let input = include_bytes!("../data/test.z.9");
+        let mut d = Decoder::new(BufReader::new(fixup(input)));
+        let mut output = [0u8; 65536];
+        let mut output_size = 0;
+        bh.iter(|| {
+            d.r = BufReader::new(fixup(input));
+            d.reset();
+            output_size = d.read(&mut output).unwrap();
+        });
+        bh.bytes = output_size as u64;
+    }
+}
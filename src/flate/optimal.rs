@@ -0,0 +1,419 @@
+//! Optimal parsing for DEFLATE, in the spirit of zopfli
+//!
+//! Instead of taking matches as they come, every match available at every
+//! position is gathered up front. The cheapest path through the input is then
+//! found by dynamic programming over an estimated bit cost of each literal and
+//! match. The symbol statistics of the resulting parse feed the costs of the
+//! next round, which converges on a parse suited to the block's own Huffman
+//! codes. Block boundaries are chosen by comparing estimated block sizes.
+
+use std::cmp;
+
+use super::encoder::{self, BitWriter, Stats, Token};
+use super::{EXTRABITS, EXTRADBITS, HISTORY};
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const NIL: u32 = u32::MAX;
+// chain entries probed at each position
+const MAX_CHAIN: usize = 8192;
+// no more blocks than this are cut out of a master block
+const MAX_BLOCKS: usize = 15;
+// smallest block (in tokens) considered for splitting
+const MIN_SPLIT: usize = 10;
+// tokens between the running statistics kept while splitting
+const STATS_STEP: usize = 1024;
+
+/// Every useful match at each position of the data being parsed. The matches
+/// of position `i` are `list[offs[i]..offs[i + 1]]`, ordered by increasing
+/// length, each with the closest distance that reaches that length.
+struct Matches {
+    offs: Vec<u32>,
+    list: Vec<(u16, u16)>,
+}
+
+impl Matches {
+    fn find(data: &[u8], start: usize, window: usize) -> Matches {
+        let hash = |i: usize| {
+            (((data[i] as usize) << 10) ^ ((data[i + 1] as usize) << 5) ^ (data[i + 2] as usize))
+                & (HASH_SIZE - 1)
+        };
+        let mut head = vec![NIL; HASH_SIZE];
+        let mut prev = vec![NIL; data.len()];
+        let mut matches = Matches {
+            offs: Vec::with_capacity(data.len() - start + 1),
+            list: Vec::new(),
+        };
+        for i in 0..data.len() {
+            if i >= start {
+                matches.offs.push(matches.list.len() as u32);
+            }
+            if i + MIN_MATCH > data.len() {
+                continue;
+            }
+            let h = hash(i);
+            if i >= start {
+                let max = cmp::min(MAX_MATCH, data.len() - i);
+                let mut best = MIN_MATCH - 1;
+                let mut cand = head[h];
+                let mut chain = MAX_CHAIN;
+                while cand != NIL && chain > 0 && i - cand as usize <= window {
+                    let c = cand as usize;
+                    if data[c + best] == data[i + best] {
+                        let len = data[c..c + max]
+                            .iter()
+                            .zip(data[i..i + max].iter())
+                            .take_while(|&(a, b)| a == b)
+                            .count();
+                        if len > best {
+                            matches.list.push((len as u16, (i - c) as u16));
+                            best = len;
+                            if len == max {
+                                break;
+                            }
+                        }
+                    }
+                    cand = prev[c];
+                    chain -= 1;
+                }
+            }
+            prev[i] = head[h];
+            head[h] = i as u32;
+        }
+        matches.offs.push(matches.list.len() as u32);
+        matches
+    }
+
+    fn at(&self, i: usize) -> &[(u16, u16)] {
+        &self.list[self.offs[i] as usize..self.offs[i + 1] as usize]
+    }
+}
+
+/// Estimated bit costs of the literal/length and distance symbols
+struct Costs {
+    lit: [f32; 256],
+    len: [f32; MAX_MATCH + 1],
+    dist: [f32; 30],
+}
+
+impl Costs {
+    /// Costs of the fixed Huffman codes, used before any statistics exist
+    fn fixed() -> Costs {
+        let mut lit = [0.0; 286];
+        for (sym, cost) in lit.iter_mut().enumerate() {
+            *cost = match sym {
+                0..=143 => 8.0,
+                144..=255 => 9.0,
+                256..=279 => 7.0,
+                _ => 8.0,
+            };
+        }
+        Costs::with(&lit, &[5.0; 30])
+    }
+
+    /// Costs following the symbol entropies of a previous parse
+    fn from_stats(stats: &Stats) -> Costs {
+        fn entropy(freqs: &[u32], costs: &mut [f32]) {
+            let total: u32 = freqs.iter().sum();
+            let total = cmp::max(total, 1) as f32;
+            for (&f, cost) in freqs.iter().zip(costs.iter_mut()) {
+                // unused symbols get a cost as if they were seen half a time
+                let f = if f == 0 { 0.5 } else { f as f32 };
+                *cost = (total / f).log2();
+            }
+        }
+        let mut lit = [0.0; 286];
+        let mut dist = [0.0; 30];
+        entropy(&stats.lit, &mut lit);
+        entropy(&stats.dist, &mut dist);
+        Costs::with(&lit, &dist)
+    }
+
+    fn with(lit: &[f32; 286], dist: &[f32; 30]) -> Costs {
+        let mut costs = Costs {
+            lit: [0.0; 256],
+            len: [0.0; MAX_MATCH + 1],
+            dist: [0.0; 30],
+        };
+        costs.lit.copy_from_slice(&lit[..256]);
+        for len in MIN_MATCH..(MAX_MATCH + 1) {
+            let lc = encoder::length_code(len);
+            costs.len[len] = lit[257 + lc] + EXTRABITS[lc] as f32;
+        }
+        for (dc, cost) in costs.dist.iter_mut().enumerate() {
+            *cost = dist[dc] + EXTRADBITS[dc] as f32;
+        }
+        costs
+    }
+}
+
+/// Distance code lookup, indexed by distance
+struct DistCodes(Vec<u8>);
+
+impl DistCodes {
+    fn new() -> DistCodes {
+        DistCodes(
+            (0..(HISTORY + 1))
+                .map(|d| encoder::dist_code(cmp::max(d, 1)) as u8)
+                .collect(),
+        )
+    }
+}
+
+/// Finds the cheapest parse of `data[start..end]` under the given costs
+fn parse(
+    data: &[u8],
+    start: usize,
+    end: usize,
+    matches: &Matches,
+    base: usize,
+    costs: &Costs,
+    dcodes: &DistCodes,
+) -> Vec<Token> {
+    let n = end - start;
+    let mut cost = vec![f32::INFINITY; n + 1];
+    // (length, distance) of the cheapest step into each position, where a
+    // length of 1 stands for a literal
+    let mut step = vec![(0u16, 0u16); n + 1];
+    cost[0] = 0.0;
+    for i in 0..n {
+        let here = cost[i];
+        let lit = here + costs.lit[data[start + i] as usize];
+        if lit < cost[i + 1] {
+            cost[i + 1] = lit;
+            step[i + 1] = (1, 0);
+        }
+        let mut lo = MIN_MATCH;
+        for &(len, dist) in matches.at(start + i - base) {
+            let len = cmp::min(len as usize, n - i);
+            let dcost = here + costs.dist[dcodes.0[dist as usize] as usize];
+            for l in lo..(len + 1) {
+                let c = dcost + costs.len[l];
+                if c < cost[i + l] {
+                    cost[i + l] = c;
+                    step[i + l] = (l as u16, dist);
+                }
+            }
+            lo = cmp::max(lo, len + 1);
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut i = n;
+    while i > 0 {
+        let (len, dist) = step[i];
+        i -= len as usize;
+        tokens.push(if len == 1 {
+            Token::Literal(data[start + i])
+        } else {
+            Token::Match(len, dist)
+        });
+    }
+    tokens.reverse();
+    tokens
+}
+
+fn token_len(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Match(len, _) => len as usize,
+    }
+}
+
+fn cost(tokens: &[Token]) -> u64 {
+    encoder::block_cost(tokens, tokens.iter().map(token_len).sum())
+}
+
+// Finds the index in `lo..hi` minimizing `f`, narrowing a set of sample
+// points down when the range is too large to be scanned fully
+fn find_minimum<F: Fn(usize) -> u64>(f: F, mut lo: usize, mut hi: usize) -> (usize, u64) {
+    const SAMPLES: usize = 9;
+    loop {
+        if hi - lo <= 1024 {
+            return (lo..hi).map(|i| (i, f(i))).min_by_key(|&(_, c)| c).unwrap();
+        }
+        let points: Vec<usize> = (0..SAMPLES)
+            .map(|k| lo + (k + 1) * (hi - lo) / (SAMPLES + 1))
+            .collect();
+        let (best, _) = points
+            .iter()
+            .enumerate()
+            .map(|(k, &p)| (k, f(p)))
+            .min_by_key(|&(_, c)| c)
+            .unwrap();
+        let new_lo = if best == 0 { lo } else { points[best - 1] };
+        let new_hi = if best == SAMPLES - 1 {
+            hi
+        } else {
+            points[best + 1]
+        };
+        lo = new_lo;
+        hi = new_hi;
+    }
+}
+
+/// Estimated sizes of blocks holding any range of a run of tokens, from the
+/// statistics of every `STATS_STEP` tokens so far. Each estimate only has to
+/// count up to `STATS_STEP` tokens, however long the range.
+struct RangeCosts<'a> {
+    tokens: &'a [Token],
+    // statistics of the first `k * STATS_STEP` tokens, at index `k`
+    totals: Vec<Stats>,
+    // bytes the tokens before each index decode to
+    pos: Vec<usize>,
+}
+
+impl<'a> RangeCosts<'a> {
+    fn new(tokens: &'a [Token]) -> RangeCosts<'a> {
+        let mut stats = Stats::new(&[]);
+        let mut totals = vec![stats.clone()];
+        let mut pos = Vec::with_capacity(tokens.len() + 1);
+        pos.push(0);
+        for (i, token) in tokens.iter().enumerate() {
+            stats.add(token);
+            if (i + 1) % STATS_STEP == 0 {
+                totals.push(stats.clone());
+            }
+            pos.push(pos[i] + token_len(token));
+        }
+        RangeCosts {
+            tokens,
+            totals,
+            pos,
+        }
+    }
+
+    // Statistics of the tokens before `end`
+    fn prefix(&self, end: usize) -> Stats {
+        let k = end / STATS_STEP;
+        let mut stats = self.totals[k].clone();
+        for token in self.tokens[k * STATS_STEP..end].iter() {
+            stats.add(token);
+        }
+        stats
+    }
+
+    fn cost(&self, lo: usize, hi: usize) -> u64 {
+        let mut stats = self.prefix(hi);
+        stats.remove(&self.prefix(lo));
+        stats.block_cost(self.pos[hi] - self.pos[lo])
+    }
+}
+
+/// Picks the token indices at which to cut a block, when splitting lowers the
+/// total estimated size
+fn split_points(tokens: &[Token]) -> Vec<usize> {
+    let costs = RangeCosts::new(tokens);
+    let mut points = vec![0, tokens.len()];
+    let mut done = vec![false];
+    while points.len() - 1 < MAX_BLOCKS {
+        // try to split the largest range that may still be split
+        let range = (0..done.len())
+            .filter(|&r| !done[r])
+            .max_by_key(|&r| points[r + 1] - points[r]);
+        let r = match range {
+            Some(r) => r,
+            None => break,
+        };
+        let (lo, hi) = (points[r], points[r + 1]);
+        if hi - lo < MIN_SPLIT {
+            done[r] = true;
+            continue;
+        }
+        let whole = costs.cost(lo, hi);
+        let (at, split) = find_minimum(|p| costs.cost(lo, p) + costs.cost(p, hi), lo + 1, hi);
+        if split >= whole {
+            done[r] = true;
+        } else {
+            points.insert(r + 1, at);
+            done.insert(r + 1, false);
+        }
+    }
+    points
+}
+
+/// Parses `data[start..]` as well as it can and writes it out as one or more
+/// blocks, the last of which is marked final if `last` is set. The bytes
+/// before `start` are history that matches may refer to, up to `window`
+/// bytes back.
+pub fn write_blocks(
+    bits: &mut BitWriter,
+    data: &[u8],
+    start: usize,
+    window: usize,
+    iterations: usize,
+    last: bool,
+) {
+    if start == data.len() {
+        encoder::write_block(bits, &[], &[], last);
+        return;
+    }
+    let matches = Matches::find(data, start, window);
+    let dcodes = DistCodes::new();
+
+    // A first parse with the fixed costs is good enough to find the blocks
+    let initial = parse(
+        data,
+        start,
+        data.len(),
+        &matches,
+        start,
+        &Costs::fixed(),
+        &dcodes,
+    );
+    let points = split_points(&initial);
+
+    let mut pos = start;
+    for (k, w) in points.windows(2).enumerate() {
+        let len: usize = initial[w[0]..w[1]].iter().map(token_len).sum();
+        let end = pos + len;
+
+        let mut best = initial[w[0]..w[1]].to_vec();
+        let mut best_cost = cost(&best);
+        let mut costs = Costs::from_stats(&Stats::new(&best));
+        for _ in 0..iterations {
+            let tokens = parse(data, pos, end, &matches, start, &costs, &dcodes);
+            let c = cost(&tokens);
+            costs = Costs::from_stats(&Stats::new(&tokens));
+            if c < best_cost {
+                best = tokens;
+                best_cost = c;
+            }
+        }
+
+        let fin = last && k == points.len() - 2;
+        encoder::write_block(bits, &best, &data[pos..end], fin);
+        pos = end;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_points, Matches, HISTORY};
+    use flate::encoder::Token;
+
+    #[test]
+    fn matches_are_closest() {
+        let data = b"abcdXabcdYabcdXabcdZ";
+        let matches = Matches::find(data, 0, HISTORY);
+        // the closest copy matches 4 bytes, the one further away matches 9
+        assert_eq!(matches.at(10), &[(4, 5), (9, 10)][..]);
+        assert!(matches.at(0).is_empty());
+    }
+
+    #[test]
+    fn split_on_content_change() {
+        let mut tokens = Vec::new();
+        for i in 0..3000 {
+            tokens.push(Token::Literal(b'a' + (i % 4) as u8));
+        }
+        for i in 0..3000 {
+            tokens.push(Token::Literal(128 + (i * 7 % 64) as u8));
+        }
+        let points = split_points(&tokens);
+        assert!(points.len() > 2);
+        assert!(points.iter().any(|&p| p > 2900 && p < 3100));
+    }
+}
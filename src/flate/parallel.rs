@@ -0,0 +1,234 @@
+//! Parallel DEFLATE compression, after pigz
+//!
+//! The input is cut into chunks which are compressed independently on worker
+//! threads. Each chunk is primed with the 32K of input preceding it as a
+//! dictionary, so that matches across chunk boundaries aren't lost, and ends
+//! with a sync flush, which leaves it at a byte boundary for the next chunk's
+//! blocks to follow. The chunks strung together in order form a single
+//! regular DEFLATE stream.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::mem;
+use std::thread::{self, JoinHandle};
+
+use super::{Encoder, HISTORY};
+
+/// Amount of input compressed by one worker at a time
+pub const CHUNK_SIZE: usize = 128 * 1024;
+
+/// A check value of the data which is computed on each chunk by the worker
+/// compressing it, and joined up in order afterwards
+pub(crate) trait Check: Send + 'static {
+    /// Check value of a piece of data
+    fn of(data: &[u8]) -> Self;
+    /// Appends the check value of the `len` bytes following
+    fn append(&mut self, next: Self, len: usize);
+}
+
+impl Check for () {
+    fn of(_: &[u8]) {}
+    fn append(&mut self, _: (), _: usize) {}
+}
+
+type Job<C> = JoinHandle<(io::Result<Vec<u8>>, C, usize)>;
+
+/// The machinery behind the parallel encoders of DEFLATE and its containers
+pub(crate) struct Parallel<W, C> {
+    pub(crate) w: W,
+    level: u8,
+    threads: usize,
+    chunk_size: usize,
+    // input waiting for a full chunk to be gathered, and the history
+    // preceding it
+    input: Vec<u8>,
+    history: Vec<u8>,
+    jobs: VecDeque<Job<C>>,
+    pub(crate) check: C,
+}
+
+impl<W: Write, C: Check> Parallel<W, C> {
+    pub(crate) fn new(w: W, level: u8, threads: usize, chunk_size: usize) -> Parallel<W, C> {
+        assert!(level <= 9, "invalid compression level {}", level);
+        assert!(threads > 0 && chunk_size > 0);
+        Parallel {
+            w,
+            level,
+            threads,
+            chunk_size,
+            input: Vec::with_capacity(chunk_size),
+            history: Vec::new(),
+            jobs: VecDeque::new(),
+            check: C::of(&[]),
+        }
+    }
+
+    // Hands the gathered input to a worker, once there's one free
+    fn submit(&mut self, last: bool) -> io::Result<()> {
+        if self.jobs.len() >= self.threads {
+            self.complete()?;
+        }
+        let data = mem::replace(&mut self.input, Vec::with_capacity(self.chunk_size));
+        let dict = self.history.clone();
+        self.history
+            .extend_from_slice(&data[data.len().saturating_sub(HISTORY)..]);
+        let excess = self.history.len().saturating_sub(HISTORY);
+        self.history.drain(..excess);
+        let level = self.level;
+        self.jobs.push_back(thread::spawn(move || {
+            let check = C::of(&data);
+            let mut e = Encoder::with_dictionary(Vec::new(), level, &dict);
+            let (out, result) = match e.write_all(&data) {
+                Ok(()) if last => e.finish(),
+                Ok(()) => e.finish_flushed(),
+                Err(err) => (Vec::new(), Err(err)),
+            };
+            (result.map(|_| out), check, data.len())
+        }));
+        Ok(())
+    }
+
+    // Writes out the output of the oldest worker
+    fn complete(&mut self) -> io::Result<()> {
+        let job = self.jobs.pop_front().unwrap();
+        let (out, check, len) = job
+            .join()
+            .map_err(|_| io::Error::other("compression thread panicked"))?;
+        self.w.write_all(&out?)?;
+        self.check.append(check, len);
+        Ok(())
+    }
+
+    pub(crate) fn write(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = cmp::min(buf.len(), self.chunk_size - self.input.len());
+            self.input.extend_from_slice(&buf[..n]);
+            buf = &buf[n..];
+            if self.input.len() == self.chunk_size {
+                self.submit(false)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        if !self.input.is_empty() {
+            self.submit(false)?;
+        }
+        while !self.jobs.is_empty() {
+            self.complete()?;
+        }
+        self.w.flush()
+    }
+
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        self.submit(true)?;
+        while !self.jobs.is_empty() {
+            self.complete()?;
+        }
+        Ok(())
+    }
+}
+
+/// A DEFLATE encoder which compresses on several threads at once, producing
+/// a single stream any decoder can read. The output is slightly larger than
+/// `Encoder`'s, as blocks can't span the chunks the input is cut into.
+pub struct ParallelEncoder<W> {
+    inner: Parallel<W, ()>,
+}
+
+impl<W: Write> ParallelEncoder<W> {
+    /// Creates a new encoder with the given compression level, see
+    /// `Encoder::with_level`, using as many threads as there are cores
+    pub fn new(w: W, level: u8) -> ParallelEncoder<W> {
+        ParallelEncoder::with_threads(w, level, default_threads(), CHUNK_SIZE)
+    }
+
+    /// Creates a new encoder compressing chunks of `chunk_size` bytes of
+    /// input on up to `threads` threads at once
+    pub fn with_threads(w: W, level: u8, threads: usize, chunk_size: usize) -> ParallelEncoder<W> {
+        ParallelEncoder {
+            inner: Parallel::new(w, level, threads, chunk_size),
+        }
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self.inner.finish();
+        (self.inner.w, result)
+    }
+}
+
+impl<W: Write> Write for ParallelEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Compresses all input so far and ends it with a sync flush, waiting
+    /// for the workers to be done with it
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Number of threads to compress on by default
+pub(crate) fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParallelEncoder;
+    use flate::{Decoder, Encoder};
+    use std::io::{Read, Write};
+
+    fn roundtrip(input: &[u8], threads: usize, chunk_size: usize) -> Vec<u8> {
+        let mut e = ParallelEncoder::with_threads(Vec::new(), 6, threads, chunk_size);
+        for piece in input.chunks(10000) {
+            e.write_all(piece).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded == input);
+        encoded
+    }
+
+    #[test]
+    fn roundtrips() {
+        let input = &include_bytes!("../data/test.large")[..300000];
+        roundtrip(b"", 4, 1000);
+        roundtrip(b"x", 4, 1000);
+        roundtrip(&input[..50000], 3, 50000);
+        let encoded = roundtrip(input, 4, 40000);
+
+        // the dictionaries keep the loss against a single stream small
+        let mut e = Encoder::with_level(Vec::new(), 6);
+        e.write_all(input).unwrap();
+        let (single, _) = e.finish();
+        assert!(encoded.len() < single.len() + single.len() / 50);
+    }
+
+    #[test]
+    fn flush() {
+        let input = include_bytes!("../data/test.txt");
+        let mut e = ParallelEncoder::with_threads(Vec::new(), 6, 2, 1000);
+        e.write_all(&input[..1500]).unwrap();
+        e.flush().unwrap();
+        e.write_all(&input[1500..]).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded[..] == input[..]);
+    }
+}
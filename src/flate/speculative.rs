@@ -0,0 +1,348 @@
+//! Parallel DEFLATE decompression, after pugz
+//!
+//! A DEFLATE stream holds nothing telling where its blocks start, and the
+//! data of each block depends on the 32K decoded before it. To decode pieces
+//! of a stream at once anyway, the start of a dynamic block is guessed at by
+//! trying to decode from every bit in turn, from some offset into the stream
+//! on. Decoding from there goes ahead without the preceding data, which back
+//! references stand in for until the piece before has been decoded and the
+//! data they refer to is known.
+//!
+//! Guessing wrong only costs time: the piece before has to end right where
+//! the next one was guessed to start, or the next one gets decoded again.
+
+use std::cmp;
+use std::io::{self, Read};
+use std::thread;
+
+use super::parallel::default_threads;
+use super::{error, Error, Inflater, State, HISTORY};
+
+// Amount of compressed data decoded by one thread at a time
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A piece of a stream decoded from one block boundary to another. Symbols
+/// below 256 are data, the others stand for the byte `symbol - 256` of the
+/// 32K preceding the piece, as long as that isn't known.
+struct Piece {
+    start: u64,
+    end: u64,
+    // whether the piece ends with the final block
+    done: bool,
+    // the context the piece was decoded with, followed by its symbols
+    out: Vec<u16>,
+    context: usize,
+}
+
+// Sets `inflater` up to decode from bit `pos` of `data`, returning what's
+// left of the data to decode
+fn seek<'a>(inflater: &mut Inflater, data: &'a [u8], pos: u64) -> &'a [u8] {
+    inflater.reset();
+    let byte = (pos / 8) as usize;
+    let bits = (pos % 8) as usize;
+    if bits == 0 {
+        return &data[byte..];
+    }
+    inflater.prime(data[byte] >> bits, 8 - bits);
+    &data[byte + 1..]
+}
+
+// Decodes a single block onto `out`
+fn block(inflater: &mut Inflater, src: &mut &[u8], out: &mut Vec<u16>) -> io::Result<()> {
+    inflater.header(src)?;
+    if let State::Stored(len) = inflater.state {
+        if src.len() < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stored block cut short",
+            ));
+        }
+        out.extend(src[..len].iter().map(|&b| b as u16));
+        *src = &src[len..];
+        inflater.end_block(src);
+        return Ok(());
+    }
+    let (lens, dists) = inflater.trees.take().unwrap();
+    let v = inflater.variant;
+    loop {
+        let sym = lens.decode(inflater, src)?;
+        match sym {
+            n if n < 256 => out.push(n),
+            256 => break,
+            n => {
+                let n = (n - 257) as usize;
+                if n >= v.extralens.len() {
+                    return error(Error::InvalidHuffmanCode);
+                }
+                let len =
+                    v.extralens[n] as usize + inflater.bits(src, v.extrabits[n] as usize)? as usize;
+                let dist = dists.decode(inflater, src)? as usize;
+                if dist >= v.extradist.len() {
+                    return error(Error::InvalidHuffmanCode);
+                }
+                let dist = v.extradist[dist] as usize
+                    + inflater.bits(src, v.extradbits[dist] as usize)? as usize;
+                if dist > out.len() {
+                    return error(Error::InvalidHuffmanCode);
+                }
+                if dist >= len {
+                    let from = out.len() - dist;
+                    out.extend_from_within(from..from + len);
+                } else {
+                    for _ in 0..len {
+                        let sym = out[out.len() - dist];
+                        out.push(sym);
+                    }
+                }
+            }
+        }
+    }
+    inflater.end_block(src);
+    Ok(())
+}
+
+// Decodes `data` block by block from bit `start`, up to the first block
+// boundary at or past bit `stop`, or the end of the stream. The context is
+// the data preceding `start` if it's known, or else unknown.
+fn decode(data: &[u8], start: u64, stop: u64, context: Option<&[u8]>) -> io::Result<Piece> {
+    let mut out: Vec<u16> = match context {
+        Some(context) => context.iter().map(|&b| b as u16).collect(),
+        None => (0..HISTORY as u16).map(|i| 256 + i).collect(),
+    };
+    let context = out.len();
+    let mut inflater = Inflater::new();
+    let mut src = seek(&mut inflater, data, start);
+    loop {
+        block(&mut inflater, &mut src, &mut out)?;
+        let end = inflater.bit_position((data.len() - src.len()) as u64);
+        if inflater.eof() || end >= stop {
+            return Ok(Piece {
+                start,
+                end,
+                done: inflater.eof(),
+                out,
+                context,
+            });
+        }
+    }
+}
+
+// Looks for the start of a dynamic block within bits `from..to` of `data`,
+// taking the first bit from which a whole block decodes
+fn find(data: &[u8], from: u64, to: u64) -> Option<u64> {
+    let bit = |pos: u64| data[(pos / 8) as usize] >> (pos % 8) & 1;
+    let limit = cmp::min(to, (data.len() as u64 * 8).saturating_sub(3));
+    let mut inflater = Inflater::new();
+    let mut out: Vec<u16> = (0..HISTORY as u16).map(|i| 256 + i).collect();
+    for pos in from..limit {
+        // a block which isn't the final one, with dynamic codes
+        if bit(pos) != 0 || bit(pos + 1) != 0 || bit(pos + 2) != 1 {
+            continue;
+        }
+        let mut src = seek(&mut inflater, data, pos);
+        out.truncate(HISTORY);
+        if block(&mut inflater, &mut src, &mut out).is_ok() {
+            return Some(pos);
+        }
+    }
+    None
+}
+
+/// A DEFLATE decoder for data in memory, which decodes pieces of the stream
+/// on several threads at once. The output is the same as `Decoder`'s, though
+/// streams without dynamic blocks gain nothing from the threads. Deflate64
+/// isn't supported.
+///
+/// This is experimental: compressed data which happens to look like the start
+/// of a block makes for wasted work, and the pieces decoded before their
+/// context is known take twice the memory of their output.
+pub struct ParallelDecoder<'a> {
+    data: &'a [u8],
+    threads: usize,
+    chunk_size: usize,
+    // the next block boundary to decode from
+    pos: u64,
+    done: bool,
+    // up to 32K of data preceding `pos`
+    history: Vec<u8>,
+    output: Vec<u8>,
+    read: usize,
+}
+
+impl<'a> ParallelDecoder<'a> {
+    /// Creates a new decoder of the DEFLATE stream at the start of `data`,
+    /// using as many threads as there are cores
+    pub fn new(data: &'a [u8]) -> ParallelDecoder<'a> {
+        ParallelDecoder::with_threads(data, default_threads(), CHUNK_SIZE)
+    }
+
+    /// Creates a new decoder which splits the stream into pieces of around
+    /// `chunk_size` bytes of compressed data, decoding up to `threads` pieces
+    /// at once
+    pub fn with_threads(data: &'a [u8], threads: usize, chunk_size: usize) -> ParallelDecoder<'a> {
+        assert!(threads > 0 && chunk_size > 0);
+        ParallelDecoder {
+            data,
+            threads,
+            chunk_size,
+            pos: 0,
+            done: false,
+            history: Vec::new(),
+            output: Vec::new(),
+            read: 0,
+        }
+    }
+
+    /// Returns whether the end of the stream has been reached
+    pub fn eof(&self) -> bool {
+        self.done && self.read == self.output.len()
+    }
+
+    // Decodes the next few pieces of the stream into the output
+    fn fill(&mut self) -> io::Result<()> {
+        let data = self.data;
+        let len = data.len() as u64 * 8;
+        let first = self.pos / 8;
+        let mut bounds: Vec<u64> = (1..(self.threads as u64 + 1))
+            .map(|k| (first + k * self.chunk_size as u64) * 8)
+            .take_while(|&b| b < len)
+            .collect();
+        // this time round decoding stops past the last bound, if not at the
+        // end of the stream
+        let end = if bounds.len() == self.threads {
+            bounds.pop().unwrap()
+        } else {
+            u64::MAX
+        };
+
+        // Each piece but the first starts at a block found past its offset
+        let found: Vec<Option<u64>> = thread::scope(|s| {
+            let jobs: Vec<_> = bounds
+                .iter()
+                .enumerate()
+                .map(|(k, &from)| {
+                    let to = bounds.get(k + 1).map_or(cmp::min(end, len), |&b| b);
+                    s.spawn(move || find(data, from, to))
+                })
+                .collect();
+            jobs.into_iter().map(|j| j.join().unwrap()).collect()
+        });
+        let mut starts = vec![self.pos];
+        starts.extend(found.into_iter().flatten());
+
+        let history = &self.history;
+        let pieces: Vec<io::Result<Piece>> = thread::scope(|s| {
+            let jobs: Vec<_> = starts
+                .iter()
+                .enumerate()
+                .map(|(k, &start)| {
+                    let stop = starts.get(k + 1).map_or(end, |&b| b);
+                    let context = if k == 0 { Some(&history[..]) } else { None };
+                    s.spawn(move || decode(data, start, stop, context))
+                })
+                .collect();
+            jobs.into_iter().map(|j| j.join().unwrap()).collect()
+        });
+
+        // Fill in the context of each piece from the one before it, decoding
+        // again those which didn't start where the one before ended
+        self.output.clear();
+        self.read = 0;
+        for (k, piece) in pieces.into_iter().enumerate() {
+            let piece = match piece {
+                Ok(piece) if piece.start == self.pos => piece,
+                _ => {
+                    let stop = starts.get(k + 1).map_or(end, |&b| b);
+                    decode(data, self.pos, stop, Some(&self.history))?
+                }
+            };
+            let from = self.output.len();
+            for &sym in piece.out[piece.context..].iter() {
+                let byte = if sym < 256 {
+                    sym as u8
+                } else {
+                    // the history is shorter than 32K only at the start
+                    let i = (sym - 256) as usize + self.history.len();
+                    if i < HISTORY {
+                        return error(Error::InvalidHuffmanCode);
+                    }
+                    self.history[i - HISTORY]
+                };
+                self.output.push(byte);
+            }
+            let new = &self.output[from..];
+            self.history
+                .extend_from_slice(&new[new.len().saturating_sub(HISTORY)..]);
+            let excess = self.history.len().saturating_sub(HISTORY);
+            self.history.drain(..excess);
+            self.pos = piece.end;
+            if piece.done {
+                self.done = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Read for ParallelDecoder<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.output.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+        let n = cmp::min(buf.len(), self.output.len() - self.read);
+        buf[..n].copy_from_slice(&self.output[self.read..self.read + n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ParallelDecoder;
+    use flate::{Decoder, Encoder};
+    use std::io::{Read, Write};
+
+    fn check(data: &[u8], threads: usize, chunk_size: usize) {
+        let mut expected = Vec::new();
+        Decoder::new(data).read_to_end(&mut expected).unwrap();
+        let mut d = ParallelDecoder::with_threads(data, threads, chunk_size);
+        let mut decoded = Vec::new();
+        d.read_to_end(&mut decoded).unwrap();
+        assert!(decoded == expected);
+        assert!(d.eof());
+    }
+
+    fn encode(input: &[u8], level: u8) -> Vec<u8> {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        e.write_all(input).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    #[test]
+    fn same_as_decoder() {
+        let input = &include_bytes!("../data/test.large")[..300000];
+        for &level in [0, 1, 6].iter() {
+            let encoded = encode(input, level);
+            check(&encoded, 4, 10000);
+            check(&encoded, 3, 30000);
+            check(&encoded, 1, 50000);
+        }
+        check(&[0x03, 0x00], 2, 1);
+    }
+
+    #[test]
+    fn corrupt() {
+        let input = &include_bytes!("../data/test.large")[..300000];
+        let mut encoded = encode(input, 6);
+        let len = encoded.len();
+        encoded.truncate(len * 3 / 4);
+        let mut d = ParallelDecoder::with_threads(&encoded, 4, 10000);
+        assert!(d.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! Inspection of how a DEFLATE stream is coded, block by block
+
+use std::io::{self, Read};
+
+use super::{Counted, Decoder, Token, HISTORY};
+
+/// How a block of a DEFLATE stream is coded
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockKind {
+    /// The data is stored as is
+    Stored,
+    /// Literals and matches coded with the fixed Huffman codes
+    Fixed,
+    /// Literals and matches coded with Huffman codes sent along with them
+    Dynamic,
+}
+
+/// Everything there is to know about one block of a DEFLATE stream
+#[derive(Clone, Debug)]
+pub struct Block {
+    /// How the block is coded
+    pub kind: BlockKind,
+    /// Whether the block is the final one of the stream
+    pub last: bool,
+    /// Offset in bits of the block in the compressed stream
+    pub bit_offset: u64,
+    /// Size in bits of the compressed block, header included. The final
+    /// block includes the padding up to the end of the stream's last byte.
+    pub bits: u64,
+    /// Size of the uncompressed data of the block
+    pub len: usize,
+    /// Lengths of the literal/length codes of a compressed block, by symbol
+    pub lit_lengths: Vec<u8>,
+    /// Lengths of the distance codes of a compressed block, by symbol
+    pub dist_lengths: Vec<u8>,
+    /// The literals and matches making up a compressed block
+    pub tokens: Vec<Token>,
+}
+
+impl Block {
+    fn new() -> Block {
+        Block {
+            kind: BlockKind::Stored,
+            last: false,
+            bit_offset: 0,
+            bits: 0,
+            len: 0,
+            lit_lengths: Vec::new(),
+            dist_lengths: Vec::new(),
+            tokens: Vec::new(),
+        }
+    }
+}
+
+/// An iterator over the blocks of a DEFLATE stream, see `Decoder::trace`
+pub struct Trace<R> {
+    d: Decoder<R>,
+    // bytes consumed from the decoder's input so far
+    consumed: u64,
+    failed: bool,
+}
+
+impl<R: Read> Trace<R> {
+    /// Creates a trace of the stream which `d` is about to decode
+    pub fn new(d: Decoder<R>) -> Trace<R> {
+        Trace {
+            d,
+            consumed: 0,
+            failed: false,
+        }
+    }
+
+    fn block(&mut self) -> io::Result<Block> {
+        let d = &mut self.d;
+        let mut src = Counted {
+            inner: d.input.source(&mut d.r),
+            count: self.consumed,
+        };
+        let mut block = Block::new();
+        block.bit_offset = d.inflater.bit_position(src.count);
+        d.inflater.trace = Some(block);
+        let mut len = 0;
+        let result = loop {
+            match d.inflater.inflate(&mut src, HISTORY) {
+                Ok(n) => len += n,
+                Err(e) => break Err(e),
+            }
+            if d.inflater.at_boundary() {
+                break Ok(());
+            }
+        };
+        let mut block = d.inflater.trace.take().unwrap();
+        result?;
+        block.len = len;
+        block.bits = d.inflater.bit_position(src.count) - block.bit_offset;
+        self.consumed = src.count;
+        Ok(block)
+    }
+}
+
+impl<R: Read> Iterator for Trace<R> {
+    type Item = io::Result<Block>;
+
+    fn next(&mut self) -> Option<io::Result<Block>> {
+        if self.failed || self.d.eof() {
+            return None;
+        }
+        let block = self.block();
+        self.failed = block.is_err();
+        Some(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BlockKind;
+    use flate::{Decoder, Encoder, Token};
+    use std::io::Write;
+
+    #[test]
+    fn blocks() {
+        let input = include_bytes!("../data/test.large");
+        let input = &input[..200000];
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(b"abcabcabc").unwrap();
+        e.flush().unwrap();
+        e.write_all(input).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+
+        let blocks: Vec<_> = Decoder::new(&encoded[..])
+            .trace()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(blocks.len() > 3);
+        assert_eq!(blocks[0].len, 9);
+        assert_eq!(
+            &blocks[0].tokens[..],
+            &[
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match(6, 3),
+            ][..]
+        );
+        // the flush marker
+        assert_eq!(blocks[1].kind, BlockKind::Stored);
+        assert_eq!(blocks[1].len, 0);
+        assert!(blocks.iter().any(|b| b.kind == BlockKind::Dynamic));
+
+        let mut offset = 0;
+        let mut total = 0;
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(block.bit_offset, offset);
+            assert_eq!(block.last, i == blocks.len() - 1);
+            offset += block.bits;
+            total += block.len;
+            if block.kind == BlockKind::Dynamic {
+                assert!(block.lit_lengths.len() >= 257);
+                assert!(block.lit_lengths[256] > 0);
+            }
+            if block.kind != BlockKind::Stored {
+                let len: usize = block
+                    .tokens
+                    .iter()
+                    .map(|t| match *t {
+                        Token::Literal(_) => 1,
+                        Token::Match(len, _) => len as usize,
+                    })
+                    .sum();
+                assert_eq!(len, block.len);
+            }
+        }
+        assert_eq!(offset, encoded.len() as u64 * 8);
+        assert_eq!(total, input.len() + 9);
+    }
+
+    #[test]
+    fn corrupt() {
+        let mut trace = Decoder::new(&[0xff, 0xff][..]).trace();
+        assert!(trace.next().unwrap().is_err());
+        assert!(trace.next().is_none());
+    }
+}
@@ -0,0 +1,201 @@
+//! Per-message compression for WebSocket, as in RFC 7692 (permessage-deflate)
+//!
+//! Each message is compressed as DEFLATE blocks ending in a sync flush, with
+//! the `00 00 ff ff` of the flush's empty stored block taken off the end.
+//! With context takeover the LZ77 window carries over from one message to
+//! the next, so messages can refer back to the ones before them. Without it
+//! every message stands alone.
+//!
+//! Both sides work on whole messages in plain byte buffers; framing them is
+//! left to the WebSocket library.
+
+use std::io::{self, BufRead, Read, Write};
+
+use super::{Encoder, Inflater, State, HISTORY};
+
+// The end of the empty stored block of a sync flush
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+fn check_window_bits(bits: u8) {
+    assert!(
+        (8..=15).contains(&bits),
+        "invalid window bits {}, must be 8 to 15",
+        bits
+    );
+}
+
+/// Compresses the messages sent over a WebSocket connection
+pub struct MessageEncoder {
+    level: u8,
+    window_bits: u8,
+    context_takeover: bool,
+    encoder: Encoder<Vec<u8>>,
+}
+
+impl MessageEncoder {
+    /// Creates a new encoder compressing at the given level, with matches
+    /// reaching no more than 2^`window_bits` bytes back (the
+    /// `*_max_window_bits` parameter, from 8 to 15). With `context_takeover`,
+    /// messages may refer back to earlier ones, which the other end has to
+    /// have agreed to.
+    pub fn new(level: u8, window_bits: u8, context_takeover: bool) -> MessageEncoder {
+        check_window_bits(window_bits);
+        MessageEncoder {
+            level,
+            window_bits,
+            context_takeover,
+            encoder: MessageEncoder::encoder(level, window_bits),
+        }
+    }
+
+    fn encoder(level: u8, window_bits: u8) -> Encoder<Vec<u8>> {
+        let mut e = Encoder::with_level(Vec::new(), level);
+        e.limit_window(1 << window_bits);
+        e
+    }
+
+    /// Compresses a whole message, returning the payload to send
+    pub fn compress(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        if !self.context_takeover {
+            self.encoder = MessageEncoder::encoder(self.level, self.window_bits);
+        }
+        self.encoder.write_all(message)?;
+        self.encoder.flush()?;
+        let mut payload = self.encoder.get_mut().split_off(0);
+        let len = payload.len() - TRAILER.len();
+        debug_assert!(payload[len..] == TRAILER);
+        payload.truncate(len);
+        Ok(payload)
+    }
+}
+
+/// Decompresses the messages received over a WebSocket connection
+pub struct MessageDecoder {
+    context_takeover: bool,
+    inflater: Inflater,
+}
+
+impl MessageDecoder {
+    /// Creates a new decoder for messages compressed with a window of
+    /// 2^`window_bits` bytes (from 8 to 15), with or without context
+    /// takeover as agreed to with the other end. Messages reaching further
+    /// back than the window are rejected.
+    pub fn new(window_bits: u8, context_takeover: bool) -> MessageDecoder {
+        check_window_bits(window_bits);
+        let mut inflater = Inflater::new();
+        inflater.limit_window(1 << window_bits);
+        MessageDecoder {
+            context_takeover,
+            inflater,
+        }
+    }
+
+    /// Decompresses the payload of a whole message
+    pub fn decompress(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        if !self.context_takeover {
+            self.inflater.reset();
+        }
+        let mut src = payload.chain(&TRAILER[..]);
+        let mut message = Vec::new();
+        loop {
+            let s = &self.inflater;
+            if let State::Header = s.state {
+                if s.bitcnt == 0 && src.fill_buf()?.is_empty() {
+                    break;
+                }
+            }
+            let n = self.inflater.inflate(&mut src, HISTORY)?;
+            let len = message.len();
+            message.resize(len + n, 0);
+            self.inflater.output(&mut message[len..]);
+            if self.inflater.eof() {
+                // the message ended with a final block, the window carries
+                // on into the next one all the same
+                self.inflater.state = State::Header;
+                break;
+            }
+        }
+        Ok(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MessageDecoder, MessageEncoder};
+
+    fn messages() -> Vec<&'static [u8]> {
+        let text = &include_bytes!("../data/test.txt")[..];
+        vec![
+            b"",
+            b"Hello",
+            &text[..1000],
+            &text[500..1500],
+            b"",
+            text,
+            &text[..1000],
+        ]
+    }
+
+    #[test]
+    fn roundtrips() {
+        for &takeover in [false, true].iter() {
+            for &bits in [8, 9, 12, 15].iter() {
+                let mut e = MessageEncoder::new(6, bits, takeover);
+                let mut d = MessageDecoder::new(bits, takeover);
+                for message in messages() {
+                    let payload = e.compress(message).unwrap();
+                    assert_eq!(d.decompress(&payload).unwrap(), message);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rfc_examples() {
+        // RFC 7692 section 7.2.3.1 and 7.2.3.2: "Hello" with and without
+        // context takeover
+        let hello = [0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+        let mut d = MessageDecoder::new(15, true);
+        assert_eq!(d.decompress(&hello).unwrap(), b"Hello");
+        assert_eq!(
+            d.decompress(&[0xf2, 0x00, 0x11, 0x00, 0x00]).unwrap(),
+            b"Hello"
+        );
+
+        // 7.2.3.3: a stored block, 7.2.3.4: a final block, which is
+        // followed by padding
+        let stored = [
+            0x00, 0x05, 0x00, 0xfa, 0xff, 0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x00,
+        ];
+        assert_eq!(d.decompress(&stored).unwrap(), b"Hello");
+        let last = [0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x00];
+        assert_eq!(d.decompress(&last).unwrap(), b"Hello");
+
+        // 7.2.3.5: two blocks in one message
+        let two = [
+            0xf2, 0x48, 0x05, 0x00, 0x00, 0x00, 0xff, 0xff, 0xca, 0xc9, 0xc9, 0x07, 0x00,
+        ];
+        assert_eq!(d.decompress(&two).unwrap(), b"Hello");
+
+        // 7.2.3.6: an empty message
+        assert_eq!(d.decompress(&[0x00]).unwrap(), b"");
+        let mut e = MessageEncoder::new(6, 15, true);
+        assert_eq!(e.compress(b"").unwrap(), [0x00]);
+    }
+
+    #[test]
+    fn context_takeover() {
+        let text = include_bytes!("../data/test.txt");
+        let mut e = MessageEncoder::new(6, 15, true);
+        let first = e.compress(text).unwrap();
+        let second = e.compress(text).unwrap();
+        assert!(second.len() < first.len() / 10);
+
+        // the window is enforced on the receiving side
+        let mut d = MessageDecoder::new(15, true);
+        d.decompress(&first).unwrap();
+        assert_eq!(d.decompress(&second).unwrap(), &text[..]);
+        assert!(MessageDecoder::new(15, true).decompress(&second).is_err());
+        assert!(MessageDecoder::new(8, true).decompress(&first).is_err());
+    }
+}
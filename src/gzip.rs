@@ -0,0 +1,700 @@
+//! GZIP Compression and Decompression. Requires `gzip` feature, enabled by default
+//!
+//! This module contains an implementation of the GZIP file format, which
+//! wraps a DEFLATE stream in a header telling about the compressed file and
+//! a trailer to check the data against. A file may be made of several such
+//! members one after the other, which decode to their data concatenated.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use compress::gzip;
+//! use std::fs::File;
+//! use std::path::Path;
+//!
+//! let stream = File::open(&Path::new("path/to/file.gz")).unwrap();
+//! let mut decompressed = Vec::new();
+//! gzip::Decoder::new(stream).read_to_end(&mut decompressed);
+//! ```
+//!
+//! Compressing works the same way through the `Encoder`, which can be given
+//! the header to write:
+//!
+//! ```rust
+//! use compress::{flate, gzip};
+//! use std::io::{Read, Write};
+//!
+//! let header = gzip::Header {
+//!     name: Some(b"file.txt".to_vec()),
+//!     ..gzip::Header::default()
+//! };
+//! let mut e = gzip::Encoder::with_header(Vec::new(), flate::DEFAULT_LEVEL, header);
+//! e.write_all(b"some text").unwrap();
+//! let (encoded, result) = e.finish();
+//! result.unwrap();
+//!
+//! let mut d = gzip::Decoder::new(&encoded[..]);
+//! let mut decoded = Vec::new();
+//! d.read_to_end(&mut decoded).unwrap();
+//! assert_eq!(&decoded[..], b"some text");
+//! assert_eq!(d.header().unwrap().name, Some(b"file.txt".to_vec()));
+//! ```
+//!
+//! # Related links
+//!
+//! * http://tools.ietf.org/html/rfc1952 - RFC that this implementation is based
+//!   on
+
+use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, BufRead, Read, Write};
+
+use crate::flate::{self, Inflater, Input};
+use crate::Crc32;
+
+const ID1: u8 = 0x1f;
+const ID2: u8 = 0x8b;
+const CM_DEFLATE: u8 = 8;
+
+const FTEXT: u8 = 1 << 0;
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+const RESERVED: u8 = 0xe0;
+
+// Window of the rolling sum of the rsyncable mode, as in gzip
+const RSYNC_WIN: usize = 4096;
+
+fn invalid<T>(msg: &'static str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+/// A subfield of the extra field of a GZIP header
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subfield {
+    /// The two bytes identifying the subfield (SI1 and SI2)
+    pub id: [u8; 2],
+    /// The data of the subfield
+    pub data: Vec<u8>,
+}
+
+/// What the header of a GZIP member tells about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Whether the data is probably text (FTEXT)
+    pub text: bool,
+    /// Modification time of the original file, in seconds since the Unix
+    /// epoch, or 0 if there is none
+    pub mtime: u32,
+    /// Extra flags (XFL), telling the compression used: 2 for the best
+    /// compression, 4 for the fastest
+    pub extra_flags: u8,
+    /// The operating system the data was compressed on, 255 if unknown
+    pub os: u8,
+    /// The subfields of the extra field (FEXTRA)
+    pub extra: Vec<Subfield>,
+    /// Name of the original file (FNAME), in ISO 8859-1, without the
+    /// terminating zero
+    pub name: Option<Vec<u8>>,
+    /// A comment for humans to read (FCOMMENT), in ISO 8859-1, without the
+    /// terminating zero
+    pub comment: Option<Vec<u8>>,
+    /// Whether the header is checked by a CRC (FHCRC)
+    pub header_crc: bool,
+}
+
+impl Header {
+    /// Returns the data of the first subfield of the extra field with the
+    /// given ID
+    pub fn subfield(&self, id: [u8; 2]) -> Option<&[u8]> {
+        self.extra.iter().find(|s| s.id == id).map(|s| &s.data[..])
+    }
+
+    // The bytes of the header as written at the start of a member
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut flg = 0;
+        let flags = [
+            (self.text, FTEXT),
+            (self.header_crc, FHCRC),
+            (!self.extra.is_empty(), FEXTRA),
+            (self.name.is_some(), FNAME),
+            (self.comment.is_some(), FCOMMENT),
+        ];
+        for &(set, flag) in flags.iter() {
+            if set {
+                flg |= flag;
+            }
+        }
+        let mut out = vec![ID1, ID2, CM_DEFLATE, flg];
+        out.extend_from_slice(&self.mtime.to_le_bytes());
+        out.push(self.extra_flags);
+        out.push(self.os);
+        if !self.extra.is_empty() {
+            let xlen: usize = self.extra.iter().map(|s| 4 + s.data.len()).sum();
+            assert!(xlen <= 0xffff, "gzip extra field too long");
+            out.extend_from_slice(&(xlen as u16).to_le_bytes());
+            for s in self.extra.iter() {
+                out.extend_from_slice(&s.id);
+                out.extend_from_slice(&(s.data.len() as u16).to_le_bytes());
+                out.extend_from_slice(&s.data);
+            }
+        }
+        for string in [&self.name, &self.comment].iter() {
+            if let Some(ref string) = **string {
+                assert!(!string.contains(&0), "zero byte in gzip header string");
+                out.extend_from_slice(string);
+                out.push(0);
+            }
+        }
+        if self.header_crc {
+            let mut hash = Crc32::new();
+            hash.feed(&out);
+            out.extend_from_slice(&(hash.result() as u16).to_le_bytes());
+        }
+        out
+    }
+}
+
+impl Default for Header {
+    fn default() -> Header {
+        Header {
+            text: false,
+            mtime: 0,
+            extra_flags: 0,
+            os: 255,
+            extra: Vec::new(),
+            name: None,
+            comment: None,
+            header_crc: false,
+        }
+    }
+}
+
+// Reads a zero-terminated string onto the raw header
+fn read_string<B: BufRead>(src: &mut B, raw: &mut Vec<u8>) -> io::Result<Vec<u8>> {
+    let start = raw.len();
+    src.read_until(0, raw)?;
+    if raw.len() == start || raw[raw.len() - 1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "gzip header cut short",
+        ));
+    }
+    Ok(raw[start..raw.len() - 1].to_vec())
+}
+
+pub(crate) fn parse_extra(mut data: &[u8]) -> io::Result<Vec<Subfield>> {
+    let mut extra = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return invalid("invalid gzip extra field");
+        }
+        let len = u16::from_le_bytes([data[2], data[3]]) as usize;
+        if data.len() < 4 + len {
+            return invalid("invalid gzip extra field");
+        }
+        extra.push(Subfield {
+            id: [data[0], data[1]],
+            data: data[4..4 + len].to_vec(),
+        });
+        data = &data[4 + len..];
+    }
+    Ok(extra)
+}
+
+/// Reads the header of a member
+fn read_header<B: BufRead>(src: &mut B) -> io::Result<Header> {
+    // everything read is kept for the header CRC
+    let mut raw = vec![0; 10];
+    src.read_exact(&mut raw)?;
+    if raw[0] != ID1 || raw[1] != ID2 {
+        return invalid("invalid gzip header");
+    }
+    if raw[2] != CM_DEFLATE {
+        return invalid("unsupported gzip compression method");
+    }
+    let flg = raw[3];
+    if flg & RESERVED != 0 {
+        return invalid("reserved gzip flags set");
+    }
+    let mut header = Header {
+        text: flg & FTEXT != 0,
+        mtime: u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]),
+        extra_flags: raw[8],
+        os: raw[9],
+        ..Header::default()
+    };
+    if flg & FEXTRA != 0 {
+        let xlen = src.read_u16::<LittleEndian>()?;
+        raw.extend_from_slice(&xlen.to_le_bytes());
+        let start = raw.len();
+        raw.resize(start + xlen as usize, 0);
+        src.read_exact(&mut raw[start..])?;
+        header.extra = parse_extra(&raw[start..])?;
+    }
+    if flg & FNAME != 0 {
+        header.name = Some(read_string(src, &mut raw)?);
+    }
+    if flg & FCOMMENT != 0 {
+        header.comment = Some(read_string(src, &mut raw)?);
+    }
+    if flg & FHCRC != 0 {
+        let crc = src.read_u16::<LittleEndian>()?;
+        let mut hash = Crc32::new();
+        hash.feed(&raw);
+        if hash.result() as u16 != crc {
+            return invalid("invalid gzip header checksum");
+        }
+        header.header_crc = true;
+    }
+    Ok(header)
+}
+
+// Checks the trailer of a member against the CRC and length of its data
+fn check_trailer<B: BufRead>(src: &mut B, crc: u32, len: u32) -> io::Result<()> {
+    if src.read_u32::<LittleEndian>()? != crc {
+        return invalid("invalid checksum on gzip member");
+    }
+    if src.read_u32::<LittleEndian>()? != len {
+        return invalid("invalid length of gzip member");
+    }
+    Ok(())
+}
+
+/// Structure used to decode a GZIP file, member after member. The wrapped
+/// stream can be re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    r: R,
+    input: Input,
+    inflater: Inflater,
+    hash: Crc32,
+    len: u32,
+    // the header of the member being decoded
+    header: Option<Header>,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new GZIP decoder which will wrap the specified reader. All
+    /// of the reader's data is decoded as members of the file.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            input: Input::new(),
+            inflater: Inflater::new(),
+            hash: Crc32::new(),
+            len: 0,
+            header: None,
+            done: false,
+        }
+    }
+
+    /// Returns the header of the member being decoded, which is read first
+    /// if it hasn't been yet. Once all of the data has been read, this is
+    /// the header of the last member.
+    pub fn header(&mut self) -> io::Result<&Header> {
+        if self.header.is_none() {
+            let mut src = self.input.source(&mut self.r);
+            self.header = Some(read_header(&mut src)?);
+        }
+        Ok(self.header.as_ref().unwrap())
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r
+    }
+
+    /// Tests if the end of the file has been reached yet.
+    pub fn eof(&self) -> bool {
+        self.done
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while !self.done {
+            self.header()?;
+            let mut src = self.input.source(&mut self.r);
+            let n = self.inflater.read(&mut src, buf)?;
+            if n > 0 {
+                self.hash.feed(&buf[..n]);
+                self.len = self.len.wrapping_add(n as u32);
+                return Ok(n);
+            }
+            check_trailer(&mut src, self.hash.result(), self.len)?;
+            if src.fill_buf()?.is_empty() {
+                self.done = true;
+            } else {
+                // on to the next member
+                self.inflater.reset();
+                self.hash.reset();
+                self.len = 0;
+                self.header = None;
+            }
+        }
+        Ok(0)
+    }
+}
+
+// XFL of a member compressed at the given level, the way zlib tells it
+fn extra_flags(level: u8) -> u8 {
+    match level {
+        0 | 1 => 4,
+        9 => 2,
+        _ => 0,
+    }
+}
+
+// Finds content-defined points to flush at for the rsyncable mode: where
+// the last 4K of data add up to a multiple of 4096, at least 4K apart
+struct Rsync {
+    window: Vec<u8>,
+    pos: usize,
+    sum: u32,
+    // bytes since the last flush point
+    since: usize,
+}
+
+impl Rsync {
+    fn new() -> Rsync {
+        Rsync {
+            window: vec![0; RSYNC_WIN],
+            pos: 0,
+            sum: 0,
+            since: 0,
+        }
+    }
+
+    // Returns the length of the data up to the first flush point in `buf`,
+    // if there is one
+    fn next(&mut self, buf: &[u8]) -> Option<usize> {
+        for (i, &b) in buf.iter().enumerate() {
+            self.sum = self.sum - self.window[self.pos] as u32 + b as u32;
+            self.window[self.pos] = b;
+            self.pos = (self.pos + 1) % RSYNC_WIN;
+            self.since += 1;
+            if self.since >= RSYNC_WIN && self.sum & (RSYNC_WIN as u32 - 1) == 0 {
+                self.since = 0;
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Structure used to compress data into a single GZIP member. The wrapped
+/// stream can be re-acquired through `finish()`.
+pub struct Encoder<W> {
+    inner: flate::Encoder<W>,
+    hash: Crc32,
+    len: u32,
+    // the header, until it's been written
+    header: Vec<u8>,
+    rsync: Option<Rsync>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new GZIP encoder which will have its output written to the
+    /// given output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, flate::DEFAULT_LEVEL)
+    }
+
+    /// Creates a new GZIP encoder with the given compression level, see
+    /// `flate::Encoder::with_level`. The header tells nothing but the level
+    /// used.
+    pub fn with_level(w: W, level: u8) -> Encoder<W> {
+        let header = Header {
+            extra_flags: extra_flags(level),
+            ..Header::default()
+        };
+        Encoder::with_header(w, level, header)
+    }
+
+    /// Creates a new GZIP encoder with the given compression level, which
+    /// starts the member with `header`. Its fields are written as they are,
+    /// the flags following from which are set.
+    ///
+    /// # Panics
+    ///
+    /// If the extra field takes more than 64K, or if the name or comment
+    /// holds a zero byte.
+    pub fn with_header(w: W, level: u8, header: Header) -> Encoder<W> {
+        Encoder {
+            inner: flate::Encoder::with_level(w, level),
+            hash: Crc32::new(),
+            len: 0,
+            header: header.to_bytes(),
+            rsync: None,
+        }
+    }
+
+    /// Creates a new GZIP encoder like `with_header`, which compresses in a
+    /// way friendly to rsync, like `gzip --rsyncable`. The compressed data is
+    /// flushed to a byte boundary wherever a rolling checksum of the input
+    /// says so. As these points only depend on the data around them, a small
+    /// change to the input only changes the output up to the next one or
+    /// two, instead of all of the rest of it. This costs a little
+    /// compression.
+    pub fn rsyncable(w: W, level: u8, header: Header) -> Encoder<W> {
+        let mut e = Encoder::with_header(w, level, header);
+        e.rsync = Some(Rsync::new());
+        e
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header.is_empty() {
+            self.inner.get_mut().write_all(&self.header)?;
+            self.header.clear();
+        }
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The member is finished up (final bytes are written), and then
+    /// the wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        if let Err(e) = self.write_header() {
+            return (self.inner.finish().0, Err(e));
+        }
+        let (crc, len) = (self.hash.result(), self.len);
+        let (mut w, result) = self.inner.finish();
+        let result = result
+            .and_then(|_| w.write_u32::<LittleEndian>(crc))
+            .and_then(|_| w.write_u32::<LittleEndian>(len));
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        let mut rest = buf;
+        while let Some(n) = self.rsync.as_mut().and_then(|r| r.next(rest)) {
+            self.inner.write_all(&rest[..n])?;
+            self.inner.sync_flush()?;
+            rest = &rest[n..];
+        }
+        self.inner.write_all(rest)?;
+        self.hash.feed(buf);
+        self.len = self.len.wrapping_add(buf.len() as u32);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decoder, Encoder, Header, Subfield};
+    use crate::flate;
+    use crate::Crc32;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use std::io::{Read, Write};
+
+    fn decode(data: &[u8]) -> Vec<u8> {
+        let mut d = Decoder::new(data);
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(d.eof());
+        buf
+    }
+
+    // A member with the given header flags and fields following the fixed
+    // part of the header
+    fn member(data: &[u8], flg: u8, fields: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x1f, 0x8b, 8, flg, 1, 2, 3, 4, 0, 3];
+        out.extend_from_slice(fields);
+        let mut e = flate::Encoder::new(out);
+        e.write_all(data).unwrap();
+        let (mut out, result) = e.finish();
+        result.unwrap();
+        let mut hash = Crc32::new();
+        hash.feed(data);
+        out.write_u32::<LittleEndian>(hash.result()).unwrap();
+        out.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+        out
+    }
+
+    #[test]
+    fn decode_gzip() {
+        let data = include_bytes!("data/test.txt.gz");
+        let text = include_bytes!("data/test.txt");
+        assert!(decode(data) == text[..]);
+
+        let mut d = Decoder::new(&data[..]);
+        assert_eq!(
+            *d.header().unwrap(),
+            Header {
+                mtime: 1584891291,
+                extra_flags: 2,
+                os: 3,
+                name: Some(b"test.txt".to_vec()),
+                ..Header::default()
+            }
+        );
+    }
+
+    #[test]
+    fn multiple_members() {
+        let text = &include_bytes!("data/test.txt")[..];
+        let mut data = include_bytes!("data/test.txt.gz").to_vec();
+        data.extend(member(b"", 0, &[]));
+        data.extend(member(b"more", 0, &[]));
+        let mut d = Decoder::new(&data[..]);
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(buf == [text, b"more"].concat());
+        assert_eq!(d.header().unwrap().name, None);
+    }
+
+    #[test]
+    fn header_fields() {
+        // FTEXT, FEXTRA, FNAME, FCOMMENT and FHCRC
+        let mut fields = vec![10, 0, b'A', b'B', 2, 0, 7, 8, b'C', b'D', 0, 0];
+        fields.extend_from_slice(b"name\0comment\0");
+        let mut head = vec![0x1f, 0x8b, 8, 0x1f, 1, 2, 3, 4, 0, 3];
+        head.extend_from_slice(&fields);
+        let mut hash = Crc32::new();
+        hash.feed(&head);
+        fields
+            .write_u16::<LittleEndian>(hash.result() as u16)
+            .unwrap();
+        let data = member(b"hello", 0x1f, &fields);
+
+        let mut d = Decoder::new(&data[..]);
+        let header = d.header().unwrap().clone();
+        assert!(header.text && header.header_crc);
+        assert_eq!(header.mtime, 0x04030201);
+        assert_eq!(
+            header.extra,
+            vec![
+                Subfield {
+                    id: *b"AB",
+                    data: vec![7, 8],
+                },
+                Subfield {
+                    id: *b"CD",
+                    data: vec![],
+                },
+            ]
+        );
+        assert_eq!(header.subfield(*b"AB"), Some(&[7, 8][..]));
+        assert_eq!(header.name, Some(b"name".to_vec()));
+        assert_eq!(header.comment, Some(b"comment".to_vec()));
+        assert_eq!(decode(&data), b"hello");
+
+        // a wrong header CRC
+        let mut bad = data.clone();
+        bad[10 + fields.len() - 1] ^= 1;
+        assert!(Decoder::new(&bad[..]).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupt() {
+        let data = member(b"hello", 0, &[]);
+        let n = data.len();
+        for &(i, bit) in [(0, 1), (2, 1), (3, 0x20), (n - 8, 1), (n - 4, 1)].iter() {
+            let mut bad = data.clone();
+            bad[i] ^= bit;
+            assert!(Decoder::new(&bad[..]).read_to_end(&mut Vec::new()).is_err());
+        }
+        assert!(Decoder::new(&data[..n - 1])
+            .read_to_end(&mut Vec::new())
+            .is_err());
+        assert!(Decoder::new(&b""[..]).read_to_end(&mut Vec::new()).is_err());
+    }
+
+    fn encode(e: Encoder<Vec<u8>>, data: &[u8]) -> Vec<u8> {
+        let mut e = e;
+        for chunk in data.chunks(1000) {
+            e.write_all(chunk).unwrap();
+        }
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        encoded
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = &include_bytes!("data/test.txt")[..];
+        for level in 0..10 {
+            let encoded = encode(Encoder::with_level(Vec::new(), level), text);
+            assert!(decode(&encoded) == text);
+        }
+        assert!(decode(&encode(Encoder::new(Vec::new()), b"")).is_empty());
+    }
+
+    #[test]
+    fn header_written() {
+        let header = Header {
+            text: true,
+            mtime: 1584891291,
+            extra_flags: 2,
+            os: 3,
+            extra: vec![Subfield {
+                id: *b"AB",
+                data: vec![1, 2, 3],
+            }],
+            name: Some(b"test.txt".to_vec()),
+            comment: Some(b"a comment".to_vec()),
+            header_crc: true,
+        };
+        let encoded = encode(
+            Encoder::with_header(Vec::new(), 6, header.clone()),
+            b"hello",
+        );
+        let mut d = Decoder::new(&encoded[..]);
+        assert_eq!(*d.header().unwrap(), header);
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"hello");
+
+        // a plain header, as gzip would write it with -n at level 9
+        let encoded = encode(Encoder::with_level(Vec::new(), 9), b"");
+        assert_eq!(encoded[..10], [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 2, 255]);
+    }
+
+    // Length of the longest common suffix of two members, their trailers
+    // aside
+    fn common_suffix(a: &[u8], b: &[u8]) -> usize {
+        let (a, b) = (&a[..a.len() - 8], &b[..b.len() - 8]);
+        a.iter()
+            .rev()
+            .zip(b.iter().rev())
+            .take_while(|&(x, y)| x == y)
+            .count()
+    }
+
+    #[test]
+    fn rsyncable() {
+        let data = &include_bytes!("data/test.large")[..400000];
+        let rsyncable =
+            |data: &[u8]| encode(Encoder::rsyncable(Vec::new(), 6, Header::default()), data);
+        let original = rsyncable(data);
+        assert!(decode(&original) == data);
+        let plain = encode(Encoder::with_header(Vec::new(), 6, Header::default()), data);
+        assert!(original.len() < plain.len() * 11 / 10);
+
+        // changing a byte, and inserting some, near the start only changes
+        // the output around them
+        let mut changed = data.to_vec();
+        changed[1000] ^= 1;
+        let encoded = rsyncable(&changed);
+        assert!(decode(&encoded) == changed);
+        assert!(common_suffix(&original, &encoded) > original.len() * 9 / 10);
+        let mut inserted = data.to_vec();
+        inserted.splice(5000..5000, b"inserted".iter().cloned());
+        let encoded = rsyncable(&inserted);
+        assert!(decode(&encoded) == inserted);
+        assert!(common_suffix(&original, &encoded) > original.len() * 9 / 10);
+    }
+}
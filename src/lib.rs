@@ -13,25 +13,39 @@ extern crate log;
 #[cfg(feature = "unstable")]
 extern crate test;
 
-use std::io::{self, Read};
+use std::io::{self, BufRead, Read};
 
 /// Public exports
 #[cfg(feature = "checksum")]
 pub use self::checksum::adler::State32 as Adler32;
+#[cfg(feature = "checksum")]
+pub use self::checksum::crc32::State32 as Crc32;
 
 #[cfg(feature = "checksum")]
 /// Checksum algorithms. Requires `checksum` feature, enabled by default
 // http://en.wikipedia.org/wiki/Checksum
 pub mod checksum {
+    pub use self::stream::{Checksum, ChecksumReader, ChecksumWriter};
+
     pub mod adler;
+    pub mod crc;
+    pub mod crc32;
+    pub mod xxhash;
+    mod stream;
 }
 
+#[cfg(feature = "bgzf")]
+pub mod bgzf;
+
 #[cfg(feature = "bwt")]
 pub mod bwt;
 
 #[cfg(feature = "flate")]
 pub mod flate;
 
+#[cfg(feature = "gzip")]
+pub mod gzip;
+
 #[cfg(feature = "lz4")]
 pub mod lz4;
 
@@ -67,3 +81,21 @@ pub trait ReadExact: Read + Sized {
 }
 
 impl<T> ReadExact for T where T: Read + Sized {}
+
+/// Consumes `amt` bytes from `inner`, handing them to `f` first. The bytes
+/// are those the last `fill_buf` returned, which are still buffered, so
+/// getting at them again doesn't read anything.
+///
+/// # Panics
+///
+/// If `inner` fails to return the data it has buffered.
+#[cfg(any(feature = "checksum", feature = "flate"))]
+pub(crate) fn consume_with<B: BufRead, F: FnOnce(&[u8])>(inner: &mut B, amt: usize, f: F) {
+    if amt > 0 {
+        let buf = inner
+            .fill_buf()
+            .expect("buffered data should be available again");
+        f(&buf[..amt]);
+    }
+    inner.consume(amt);
+}
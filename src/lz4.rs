@@ -35,6 +35,7 @@ use std::ptr::copy_nonoverlapping;
 use std::vec::Vec;
 
 use super::byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use super::checksum::xxhash::{self, State32};
 use super::ReadExact;
 
 const MAGIC: u32 = 0x184d2204;
@@ -272,7 +273,7 @@ impl<'a> BlockEncoder<'a> {
                     }
 
                     if step > 1 {
-                        self.hash_table[hash as usize] = r - UNINITHASH;
+                        self.hash_table[hash as usize] = (Wrapping(r) - Wrapping(UNINITHASH)).0;
                         self.pos -= step - 1;
                         step = 1;
                         continue;
@@ -342,6 +343,7 @@ pub struct Decoder<R> {
     blk_checksum: bool,
     stream_checksum: bool,
     max_block_size: usize,
+    hash: State32,
 }
 
 impl<R: Read + Sized> Decoder<R> {
@@ -360,6 +362,7 @@ impl<R: Read + Sized> Decoder<R> {
             end: 0,
             eof: false,
             max_block_size: 0,
+            hash: State32::new(),
         }
     }
 
@@ -370,6 +373,7 @@ impl<R: Read + Sized> Decoder<R> {
         self.eof = false;
         self.start = 0;
         self.end = 0;
+        self.hash.reset();
     }
 
     fn read_header(&mut self) -> io::Result<()> {
@@ -378,10 +382,11 @@ impl<R: Read + Sized> Decoder<R> {
             return Err(io::Error::new(io::ErrorKind::InvalidInput, ""));
         }
 
-        let mut bits = [0; 3];
-        r#try!(self.r.read(&mut bits[..2]));
-        let flg = bits[0];
-        let bd = bits[1];
+        // the descriptor is kept for its checksum
+        let mut descriptor = Vec::with_capacity(10);
+        self.r.push_exactly(2, &mut descriptor)?;
+        let flg = descriptor[0];
+        let bd = descriptor[1];
 
         // bits 7/6, the version number. Right now this must be 1
         if (flg >> 6) != 0b01 {
@@ -416,7 +421,8 @@ impl<R: Read + Sized> Decoder<R> {
 
         // read off other portions of the stream
         let size = if stream_size {
-            Some(r#try!(self.r.read_u64::<LittleEndian>()))
+            self.r.push_exactly(8, &mut descriptor)?;
+            Some((&descriptor[2..]).read_u64::<LittleEndian>().unwrap())
         } else {
             None
         };
@@ -429,20 +435,37 @@ impl<R: Read + Sized> Decoder<R> {
 
         self.max_block_size = max_block_size;
 
-        // XXX: implement checksums
-        let cksum = r#try!(self.r.read_u8());
-        debug!("ignoring header checksum: {}", cksum);
+        // the second byte of the descriptor's hash
+        let cksum = self.r.read_u8()?;
+        if cksum != (xxhash::xxh32(&descriptor, 0) >> 8) as u8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid LZ4 header checksum",
+            ));
+        }
         return Ok(());
     }
 
     fn decode_block(&mut self) -> io::Result<bool> {
         match r#try!(self.r.read_u32::<LittleEndian>()) {
             // final block, we're done here
-            0 => return Ok(false),
+            0 => {
+                if self.stream_checksum {
+                    let cksum = self.r.read_u32::<LittleEndian>()?;
+                    if cksum != self.hash.result() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "invalid LZ4 content checksum",
+                        ));
+                    }
+                }
+                return Ok(false);
+            }
 
             // raw block to read
             n if n & 0x80000000 != 0 => {
                 let amt = (n & 0x7fffffff) as usize;
+                self.temp.truncate(0);
                 self.output.truncate(0);
                 self.output.reserve(amt);
                 r#try!(self.r.push_exactly(amt as u64, &mut self.output));
@@ -473,8 +496,22 @@ impl<R: Read + Sized> Decoder<R> {
         }
 
         if self.blk_checksum {
-            let cksum = r#try!(self.r.read_u32::<LittleEndian>());
-            debug!("ignoring block checksum {}", cksum);
+            // the checksum is that of the block as stored
+            let cksum = self.r.read_u32::<LittleEndian>()?;
+            let block = if self.temp.is_empty() {
+                &self.output[..self.end]
+            } else {
+                &self.temp[..]
+            };
+            if cksum != xxhash::xxh32(block, 0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid LZ4 block checksum",
+                ));
+            }
+        }
+        if self.stream_checksum {
+            self.hash.feed(&self.output[..self.end]);
         }
         return Ok(true);
     }
@@ -524,15 +561,13 @@ pub struct Encoder<W> {
     tmp: Vec<u8>,
     wrote_header: bool,
     limit: usize,
+    hash: State32,
 }
 
 impl<W: Write> Encoder<W> {
     /// Creates a new encoder which will have its output written to the given
     /// output stream. The output stream can be re-acquired by calling
     /// `finish()`
-    ///
-    /// NOTE: compression isn't actually implemented just yet, this is just a
-    /// skeleton of a future implementation.
     pub fn new(w: W) -> Encoder<W> {
         Encoder {
             w: w,
@@ -540,6 +575,7 @@ impl<W: Write> Encoder<W> {
             buf: Vec::with_capacity(1024),
             tmp: Vec::new(),
             limit: 256 * 1024,
+            hash: State32::new(),
         }
     }
 
@@ -547,19 +583,22 @@ impl<W: Write> Encoder<W> {
         self.tmp.truncate(0);
         if self.compress() {
             r#try!(self.w.write_u32::<LittleEndian>(self.tmp.len() as u32));
-            r#try!(self.w.write(&self.tmp));
+            self.w.write_all(&self.tmp)?;
         } else {
             r#try!(self
                 .w
                 .write_u32::<LittleEndian>((self.buf.len() as u32) | 0x80000000));
-            r#try!(self.w.write(&self.buf));
+            self.w.write_all(&self.buf)?;
         }
         self.buf.truncate(0);
         Ok(())
     }
 
+    // Compresses the buffered data into `tmp`, returning whether that's
+    // any smaller than storing it raw
     fn compress(&mut self) -> bool {
-        false
+        let len = encode_block(&self.buf, &mut self.tmp);
+        len > 0 && len < self.buf.len()
     }
 
     /// This function is used to flag that this session of compression is done
@@ -568,8 +607,10 @@ impl<W: Write> Encoder<W> {
     pub fn finish(mut self) -> (W, io::Result<()>) {
         let mut result = self.flush();
 
-        for _ in 0..2 {
-            let tmp = self.w.write_u32::<LittleEndian>(0);
+        // the end mark, followed by the content checksum
+        let cksum = self.hash.result();
+        for &word in [0, cksum].iter() {
+            let tmp = self.w.write_u32::<LittleEndian>(word);
 
             result = result.and_then(|_| tmp);
         }
@@ -579,27 +620,29 @@ impl<W: Write> Encoder<W> {
 }
 
 impl<W: Write> Write for Encoder<W> {
-    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         if !self.wrote_header {
             r#try!(self.w.write_u32::<LittleEndian>(MAGIC));
-            // version 01, turn on block independence, but turn off
-            // everything else (we have no checksums right now).
-            r#try!(self.w.write_u8(0b01_100000));
+            // version 01, turn on block independence and the content
+            // checksum, but turn off everything else.
             // Maximum block size is 256KB
-            r#try!(self.w.write_u8(0b0_101_0000));
-            // XXX: this checksum is just plain wrong.
-            r#try!(self.w.write_u8(0));
+            let descriptor = [0b01_100100, 0b0_101_0000];
+            self.w.write_all(&descriptor)?;
+            let cksum = (xxhash::xxh32(&descriptor, 0) >> 8) as u8;
+            self.w.write_u8(cksum)?;
             self.wrote_header = true;
         }
+        self.hash.feed(buf);
 
-        while buf.len() > 0 {
-            let amt = cmp::min(self.limit - self.buf.len(), buf.len());
-            self.buf.extend(buf[..amt].iter().map(|b| *b));
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let amt = cmp::min(self.limit - self.buf.len(), rest.len());
+            self.buf.extend_from_slice(&rest[..amt]);
 
             if self.buf.len() == self.limit {
-                r#try!(self.encode_block());
+                self.encode_block()?;
             }
-            buf = &buf[amt..];
+            rest = &rest[amt..];
         }
 
         Ok(buf.len())
@@ -673,6 +716,23 @@ mod test {
         test_decode(include_bytes!("data/test.lz4.9"), reference);
     }
 
+    #[test]
+    fn checksums() {
+        let reference = include_bytes!("data/test.txt");
+        // with block checksums as well
+        test_decode(include_bytes!("data/test.lz4.blockcheck"), reference);
+
+        let input = include_bytes!("data/test.lz4.blockcheck");
+        let n = input.len();
+        // the header, block and content checksums
+        for &i in [6, n - 12, n - 1].iter() {
+            let mut corrupt = input.to_vec();
+            corrupt[i] ^= 1;
+            let mut d = Decoder::new(BufReader::new(&corrupt[..]));
+            assert!(d.read_to_end(&mut Vec::new()).is_err());
+        }
+    }
+
     #[test]
     fn raw_encode_block() {
         let data = include_bytes!("data/test.txt");
@@ -738,6 +798,34 @@ mod test {
         roundtrip(b"test");
         roundtrip(b"");
         roundtrip(include_bytes!("data/test.txt"));
+        roundtrip(&include_bytes!("data/test.large")[..1000000]);
+        // already compressed, so that matches are rare and short
+        roundtrip(&include_bytes!("data/test.large.z.5")[..1000000]);
+    }
+
+    fn encode(bytes: &[u8]) -> Vec<u8> {
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(bytes).unwrap();
+        let (encoded, err) = e.finish();
+        err.unwrap();
+        encoded
+    }
+
+    #[test]
+    fn compresses() {
+        let text = include_bytes!("data/test.txt");
+        assert!(encode(text).len() < text.len());
+        let large = &include_bytes!("data/test.large")[..1000000];
+        assert!(encode(large).len() < large.len() * 9 / 10);
+
+        // data which doesn't compress is stored raw, costing only the
+        // framing
+        let noise: Vec<u8> = (0..300000).map(|_| rand::random::<u8>()).collect();
+        let encoded = encode(&noise);
+        assert_eq!(encoded.len(), noise.len() + 7 + 2 * 4 + 4 + 4);
+        let mut decoded = Vec::new();
+        Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+        assert!(decoded == noise);
     }
 
     #[cfg(feature = "unstable")]
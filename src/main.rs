@@ -12,17 +12,20 @@ extern crate byteorder;
 
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
-use std::{env, str};
-use compress::{bwt, lz4, ReadExact};
+use std::{env, process, str};
+use compress::{bwt, flate, lz4, ReadExact};
 use compress::entropy::ari;
 use byteorder::{LittleEndian, WriteBytesExt, ReadBytesExt};
 
 static MAGIC    : u32   = 0x73632172;   //=r!cs
 
+type Handler = Box<dyn FnMut(&str, &mut Config)>;
+
 struct Config {
     exe_name: String,
     methods: Vec<String>,
     block_size: usize,
+    level: u8,
     decompress: bool,
 }
 
@@ -32,14 +35,17 @@ impl Config {
             exe_name: args.next().unwrap().clone(),
             methods: Vec::new(),
             block_size: 1<<16,
+            level: flate::DEFAULT_LEVEL,
             decompress: false,
         };
-        let mut handlers: HashMap<&str, Box<dyn FnMut(&str, &mut Config)>> =
-            HashMap::new();
+        let mut handlers: HashMap<&str, Handler> = HashMap::new();
         handlers.insert("d", Box::new(|_, cfg| { cfg.decompress = true; }));
         handlers.insert("block", Box::new(|b, cfg| {
             cfg.block_size = b.parse().unwrap();
         }));
+        handlers.insert("level", Box::new(|l, cfg| {
+            cfg.level = l.parse().unwrap();
+        }));
 
         for arg in args {
 			let slice = &arg[..];
@@ -56,15 +62,63 @@ impl Config {
     }
 }
 
+/// A writer of a pass, which has to be finished to terminate its stream, as
+/// a plain `flush` may leave it open
+trait Finish: Write {
+    /// Terminates the stream, then finishes the writer it wraps
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+type Sink = Box<dyn Finish>;
+type Encode = Box<dyn FnMut(Sink, &Config) -> Sink>;
+type Decode = Box<dyn FnMut(Box<dyn Read>, &Config) -> Box<dyn Read>>;
+
+impl Finish for io::Stdout {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Finish for ari::ByteEncoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let (w, result) = ari::ByteEncoder::finish(*self);
+        result.and(w.finish())
+    }
+}
+
+impl Finish for bwt::Encoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let (w, result) = bwt::Encoder::finish(*self);
+        result.and(w.finish())
+    }
+}
+
+impl Finish for bwt::mtf::Encoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        bwt::mtf::Encoder::finish(*self).finish()
+    }
+}
+
+impl Finish for flate::Encoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let (w, result) = flate::Encoder::finish(*self);
+        result.and(w.finish())
+    }
+}
+
+impl Finish for lz4::Encoder<Sink> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let (w, result) = lz4::Encoder::finish(*self);
+        result.and(w.finish())
+    }
+}
+
 struct Pass {
-    encode: Box<dyn FnMut(Box<dyn Write + 'static>, &Config)
-                      -> Box<dyn Write + 'static> + 'static>,
-    decode: Box<dyn FnMut(Box<dyn Read + 'static>, &Config)
-                      -> Box<dyn Read + 'static> + 'static>,
+    encode: Encode,
+    decode: Decode,
     info: String,
 }
 
-
 /// main entry point
 pub fn main() {
     let mut passes: HashMap<String,Pass> = HashMap::new();
@@ -75,7 +129,7 @@ pub fn main() {
     });
     passes.insert("ari".to_string(), Pass {
         encode: Box::new(|w,_c| {
-            Box::new(ari::ByteEncoder::new(w)) as Box<dyn Write + 'static>
+            Box::new(ari::ByteEncoder::new(w)) as Sink
         }),
         decode: Box::new(|r,_c| {
             Box::new(ari::ByteDecoder::new(r)) as Box<dyn Read + 'static>
@@ -84,7 +138,7 @@ pub fn main() {
     });
     passes.insert("bwt".to_string(), Pass {
         encode: Box::new(|w,c| {
-            Box::new(bwt::Encoder::new(w, c.block_size)) as Box<dyn Write + 'static>
+            Box::new(bwt::Encoder::new(w, c.block_size)) as Sink
         }),
         decode: Box::new(|r,_c| {
             Box::new(bwt::Decoder::new(r, true)) as Box<dyn Read + 'static>
@@ -93,26 +147,25 @@ pub fn main() {
     });
     passes.insert("mtf".to_string(), Pass {
         encode: Box::new(|w,_c| {
-            Box::new(bwt::mtf::Encoder::new(w)) as Box<dyn Write + 'static>
+            Box::new(bwt::mtf::Encoder::new(w)) as Sink
         }),
         decode: Box::new(|r,_c| {
             Box::new(bwt::mtf::Decoder::new(r)) as Box<dyn Read + 'static>
         }),
         info: "Move-To-Front Transformation".to_string(),
     });
-    /* // looks like we are missing the encoder implementation
-    passes.insert(~"flate", Pass {
-        encode: |w,_c| {
-            ~flate::Encoder::new(w, true) as ~Write
-        },
-        decode: |r,_c| {
-            ~flate::Decoder::new(r, true) as ~Read
-        },
-        info: ~"Standardized Ziv-Lempel + Huffman variant",
-    });*/
+    passes.insert("flate".to_string(), Pass {
+        encode: Box::new(|w,c| {
+            Box::new(flate::Encoder::with_level(w, c.level)) as Sink
+        }),
+        decode: Box::new(|r,_c| {
+            Box::new(flate::Decoder::new(r)) as Box<dyn Read + 'static>
+        }),
+        info: "Standardized Ziv-Lempel + Huffman variant".to_string(),
+    });
     passes.insert("lz4".to_string(), Pass {
         encode: Box::new(|w,_c| {
-            Box::new(lz4::Encoder::new(w)) as Box<dyn Write + 'static>
+            Box::new(lz4::Encoder::new(w)) as Sink
         }),
         decode: Box::new(|r,_c| { // LZ4 decoder seem to work
             Box::new(lz4::Decoder::new(r)) as Box<dyn Read + 'static>
@@ -158,6 +211,7 @@ pub fn main() {
         println!("Options:");
         println!("\t-d (to decompress)");
         println!("\t-block<N> (BWT block size)");
+        println!("\t-level<N> (DEFLATE compression level, 0-9)");
         println!("Passes:");
         for (name,pa) in passes.iter() {
             println!("\t{} = {}", *name, pa.info);
@@ -169,14 +223,17 @@ pub fn main() {
             output.write_u8(met.len() as u8).unwrap();
             output.write_all(met.as_bytes()).unwrap();
         }
-        let mut wsum: Box<dyn Write> = Box::new(output);
+        let mut wsum: Sink = Box::new(output);
         for met in config.methods.iter() {
             match passes.get_mut(met) {
                 Some(pa) => wsum = (pa.encode)(wsum, &config),
                 None => panic!("Pass {} is not implemented", *met)
             }
         }
-        io::copy(&mut input, &mut wsum).unwrap();
-        wsum.flush().unwrap();
+        let result = io::copy(&mut input, &mut wsum).and(wsum.finish());
+        if let Err(e) = result {
+            eprintln!("Unable to write output: {}", e);
+            process::exit(1);
+        }
     }
 }
@@ -15,45 +15,122 @@
 //! lib::Decoder::new(stream).read_to_end(&mut decompressed);
 //! ```
 //!
+//! Compressing works the same way through the `Encoder`:
+//!
+//! ```rust
+//! use compress::zlib;
+//! use std::io::{Read, Write};
+//!
+//! let mut e = zlib::Encoder::new(Vec::new());
+//! e.write_all(b"some text").unwrap();
+//! let (encoded, result) = e.finish();
+//! result.unwrap();
+//!
+//! let mut decoded = Vec::new();
+//! zlib::Decoder::new(&encoded[..]).read_to_end(&mut decoded).unwrap();
+//! assert_eq!(&decoded[..], b"some text");
+//! ```
+//!
 //! # Related links
 //!
 //! * http://tools.ietf.org/html/rfc1950 - RFC that this implementation is based
 //!   on
 
-use super::byteorder::{BigEndian, ReadBytesExt};
-use std::io::{self, Read};
+use super::byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, BufRead, Read, Write};
 
-use crate::flate;
+use crate::flate::parallel::{self, Check, Parallel};
+use crate::flate::{self, Inflater, Input, CHUNK_SIZE};
 use crate::Adler32;
 
-/// Structure used to decode a ZLIB-encoded stream. The wrapped stream can be
-/// re-acquired through the unwrap() method.
-pub struct Decoder<R> {
+// FLEVEL of a stream compressed at the given level, the way zlib tells it
+fn flevel(level: u8) -> u16 {
+    match level {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    }
+}
+
+// Header of a stream with a 32K window and the given FLEVEL, which may be
+// compressed against a preset dictionary (FDICT), the ID of which follows
+fn header(flevel: u16, fdict: bool) -> [u8; 2] {
+    let cmf = 0x78u16;
+    let flg = flevel << 6 | (fdict as u16) << 5;
+    let check = (31 - (cmf << 8 | flg) % 31) % 31;
+    [cmf as u8, (flg | check) as u8]
+}
+
+/// The compression level a ZLIB stream tells it was compressed at (FLEVEL).
+/// This is only informative.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level {
+    /// The fastest algorithm
+    Fastest,
+    /// A fast algorithm
+    Fast,
+    /// The default algorithm
+    Default,
+    /// The maximum compression, slowest algorithm
+    Maximum,
+}
+
+impl Level {
+    // The value of FLEVEL telling this level
+    fn flevel(self) -> u16 {
+        match self {
+            Level::Fastest => 0,
+            Level::Fast => 1,
+            Level::Default => 2,
+            Level::Maximum => 3,
+        }
+    }
+}
+
+/// What the header of a ZLIB stream tells about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Size of the window the stream was compressed with, from 256 bytes to
+    /// 32K. No data further back than that is referred to.
+    pub window_size: usize,
+    /// The compression level
+    pub level: Level,
+    /// The Adler-32 of the preset dictionary the stream was compressed
+    /// against, if any
+    pub dictionary: Option<u32>,
+}
+
+/// The state of decoding a ZLIB stream, read from whatever `BufRead` is
+/// handed in
+struct Stream {
     hash: Adler32,
-    inner: flate::Decoder<R>,
-    read_header: bool,
+    inflater: Inflater,
+    header: Option<Header>,
+    done: bool,
+    dictionary: Option<Vec<u8>>,
 }
 
-impl<R: Read> Decoder<R> {
-    /// Creates a new ZLIB-stream decoder which will wrap the specified reader.
-    /// This decoder also implements the `Reader` trait, and the underlying
-    /// reader can be re-acquired through the `unwrap` method.
-    pub fn new(r: R) -> Decoder<R> {
-        Decoder {
+impl Stream {
+    fn new() -> Stream {
+        Stream {
             hash: Adler32::new(),
-            inner: flate::Decoder::new(r),
-            read_header: false,
+            inflater: Inflater::new(),
+            header: None,
+            done: false,
+            dictionary: None,
         }
     }
 
-    /// Destroys this decoder, returning the underlying reader.
-    pub fn unwrap(self) -> R {
-        self.inner.r
+    fn with_dictionary(dict: &[u8]) -> Stream {
+        let mut stream = Stream::new();
+        stream.dictionary = Some(dict.to_vec());
+        stream
     }
 
-    fn validate_header(&mut self) -> io::Result<()> {
-        let cmf = r#try!(self.inner.r.read_u8());
-        let flg = r#try!(self.inner.r.read_u8());
+    fn validate_header<B: BufRead>(&mut self, src: &mut B) -> io::Result<Header> {
+        let cmf = src.read_u8()?;
+        let flg = src.read_u8()?;
         if cmf & 0xf != 0x8 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -61,54 +138,77 @@ impl<R: Read> Decoder<R> {
             ));
         }
 
-        if cmf & 0xf0 != 0x70 {
+        if cmf >> 4 > 7 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "unsupported zlib window size",
+                "invalid zlib window size",
             ));
         }
 
-        if flg & 0x20 != 0 {
+        if ((cmf as u16) * 256 + (flg as u16)) % 31 != 0 {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "unsupported initial dictionary in the output stream",
+                "invalid zlib header checksum",
             ));
         }
 
-        if ((cmf as u16) * 256 + (flg as u16)) % 31 != 0 {
+        let mut header = Header {
+            window_size: 1 << ((cmf >> 4) + 8),
+            level: match flg >> 6 {
+                0 => Level::Fastest,
+                1 => Level::Fast,
+                2 => Level::Default,
+                _ => Level::Maximum,
+            },
+            dictionary: None,
+        };
+        self.inflater.limit_window(header.window_size);
+        if flg & 0x20 == 0 {
+            self.inflater.set_dictionary(&[]);
+            return Ok(header);
+        }
+        // The stream names its preset dictionary by the dictionary's Adler-32
+        let id = src.read_u32::<BigEndian>()?;
+        header.dictionary = Some(id);
+        let dict = match self.dictionary {
+            Some(ref dict) => dict,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "zlib stream needs a preset dictionary",
+                ))
+            }
+        };
+        let mut hash = Adler32::new();
+        hash.feed(dict);
+        if hash.result() != id {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "invalid zlib header checksum",
+                "wrong preset dictionary for zlib stream",
             ));
         }
-        Ok(())
+        self.inflater.set_dictionary(dict);
+        Ok(header)
     }
 
-    /// Tests if this stream has reached the EOF point yet.
-    pub fn eof(&self) -> bool {
-        self.inner.eof()
-    }
-
-    #[allow(dead_code)]
-    fn reset(&mut self) {
-        self.inner.reset();
-        self.hash.reset();
-        self.read_header = false;
+    fn header<B: BufRead>(&mut self, src: &mut B) -> io::Result<&Header> {
+        if self.header.is_none() {
+            self.header = Some(self.validate_header(src)?);
+        }
+        Ok(self.header.as_ref().unwrap())
     }
-}
 
-impl<R: Read> Read for Decoder<R> {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if !self.read_header {
-            r#try!(self.validate_header());
-            self.read_header = true;
-        } else if self.inner.eof() {
+    fn read<B: BufRead>(&mut self, src: &mut B, buf: &mut [u8]) -> io::Result<usize> {
+        self.header(src)?;
+        if self.done {
             return Ok(0);
         }
-        match self.inner.read(buf) {
-            Ok(0) => {
-                let cksum = r#try!(self.inner.r.read_u32::<BigEndian>());
-                if cksum != self.hash.result() {
+        match self.inflater.read(src, buf)? {
+            0 => {
+                let mut cksum = [0; 4];
+                src.read_exact(&mut cksum)?;
+                self.done = true;
+                if u32::from_be_bytes(cksum) != self.hash.result() {
                     Err(io::Error::new(
                         io::ErrorKind::InvalidInput,
                         "invalid checksum on zlib stream",
@@ -117,19 +217,367 @@ impl<R: Read> Read for Decoder<R> {
                     Ok(0)
                 }
             }
-            Ok(n) => {
+            n => {
                 self.hash.feed(&buf[..n]);
                 Ok(n)
             }
-            Err(e) => Err(e),
         }
     }
+
+    fn reset(&mut self) {
+        self.inflater.reset();
+        self.hash.reset();
+        self.header = None;
+        self.done = false;
+    }
+}
+
+/// Structure used to decode a ZLIB-encoded stream. The wrapped stream can be
+/// re-acquired through the unwrap() method.
+pub struct Decoder<R> {
+    r: R,
+    input: Input,
+    stream: Stream,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Creates a new ZLIB-stream decoder which will wrap the specified reader.
+    /// This decoder also implements the `Reader` trait, and the underlying
+    /// reader can be re-acquired through the `unwrap` method.
+    pub fn new(r: R) -> Decoder<R> {
+        Decoder {
+            r,
+            input: Input::new(),
+            stream: Stream::new(),
+        }
+    }
+
+    /// Creates a new ZLIB-stream decoder which provides the given preset
+    /// dictionary to streams asking for one. The stream identifies the
+    /// dictionary it was compressed with, which has to match.
+    pub fn with_dictionary(r: R, dict: &[u8]) -> Decoder<R> {
+        Decoder {
+            r,
+            input: Input::new(),
+            stream: Stream::with_dictionary(dict),
+        }
+    }
+
+    /// Returns the header of the stream, which is read first if it hasn't
+    /// been yet
+    pub fn header(&mut self) -> io::Result<&Header> {
+        let mut src = self.input.source(&mut self.r);
+        self.stream.header(&mut src)
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r
+    }
+
+    /// Returns the bytes which were read from the wrapped reader but follow
+    /// the end of the ZLIB stream, once it has been read to the end. The rest
+    /// of the data after the stream is still to be read from the reader.
+    pub fn trailing(&self) -> &[u8] {
+        self.input.remaining()
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.stream.inflater.eof()
+    }
+
+    #[allow(dead_code)]
+    fn reset(&mut self) {
+        self.input.clear();
+        self.stream.reset();
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut src = self.input.source(&mut self.r);
+        self.stream.read(&mut src, buf)
+    }
+}
+
+/// Decodes a ZLIB stream from a buffered reader, consuming exactly the bytes
+/// of the stream from it. Once the stream has been read to the end, including
+/// its checksum, the reader is positioned right behind it.
+pub struct BufDecoder<R> {
+    r: R,
+    stream: Stream,
+}
+
+impl<R: BufRead> BufDecoder<R> {
+    /// Creates a new ZLIB-stream decoder which will wrap the specified
+    /// buffered reader.
+    pub fn new(r: R) -> BufDecoder<R> {
+        BufDecoder {
+            r,
+            stream: Stream::new(),
+        }
+    }
+
+    /// Creates a new ZLIB-stream decoder which provides the given preset
+    /// dictionary to streams asking for one, see `Decoder::with_dictionary`.
+    pub fn with_dictionary(r: R, dict: &[u8]) -> BufDecoder<R> {
+        BufDecoder {
+            r,
+            stream: Stream::with_dictionary(dict),
+        }
+    }
+
+    /// Returns the header of the stream, which is read first if it hasn't
+    /// been yet
+    pub fn header(&mut self) -> io::Result<&Header> {
+        self.stream.header(&mut self.r)
+    }
+
+    /// Destroys this decoder, returning the underlying reader.
+    pub fn unwrap(self) -> R {
+        self.r
+    }
+
+    /// Tests if this stream has reached the EOF point yet.
+    pub fn eof(&self) -> bool {
+        self.stream.inflater.eof()
+    }
+
+    /// Resets this decoder to decode the next stream from the reader.
+    pub fn reset(&mut self) {
+        self.stream.reset();
+    }
+}
+
+impl<R: BufRead> Read for BufDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(&mut self.r, buf)
+    }
+}
+
+/// The structure that is used to produce a ZLIB stream: a header telling the
+/// compression level, the DEFLATE stream of the data, and its Adler-32. This
+/// wraps an internal writer which receives the compressed data.
+///
+/// Calling `flush` does a sync flush of the DEFLATE stream, see
+/// `flate::Encoder`.
+pub struct Encoder<W> {
+    inner: flate::Encoder<W>,
+    hash: Adler32,
+    // the header, until it's been written
+    header: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    /// Creates a new ZLIB encoder which will have its output written to the
+    /// given output stream. The output stream can be re-acquired by calling
+    /// `finish()`
+    pub fn new(w: W) -> Encoder<W> {
+        Encoder::with_level(w, flate::DEFAULT_LEVEL)
+    }
+
+    /// Creates a new ZLIB encoder with the given compression level, see
+    /// `flate::Encoder::with_level`
+    pub fn with_level(w: W, level: u8) -> Encoder<W> {
+        Encoder {
+            inner: flate::Encoder::with_level(w, level),
+            hash: Adler32::new(),
+            header: header(flevel(level), false).to_vec(),
+        }
+    }
+
+    /// Creates a new ZLIB encoder with the given compression level which
+    /// compresses against a preset dictionary, see
+    /// `flate::Encoder::with_dictionary`. The stream names the dictionary by
+    /// its Adler-32, and can only be decoded with the same dictionary.
+    pub fn with_dictionary(w: W, level: u8, dict: &[u8]) -> Encoder<W> {
+        let mut id = Adler32::new();
+        id.feed(dict);
+        let mut header = header(flevel(level), true).to_vec();
+        header.write_u32::<BigEndian>(id.result()).unwrap();
+        Encoder {
+            inner: flate::Encoder::with_dictionary(w, level, dict),
+            hash: Adler32::new(),
+            header,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header.is_empty() {
+            self.inner.get_mut().write_all(&self.header)?;
+            self.header.clear();
+        }
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        if let Err(e) = self.write_header() {
+            return (self.inner.finish().0, Err(e));
+        }
+        let cksum = self.hash.result();
+        let (mut w, result) = self.inner.finish();
+        let result = result.and_then(|_| w.write_u32::<BigEndian>(cksum));
+        (w, result)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        self.inner.write_all(buf)?;
+        self.hash.feed(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.inner.flush()
+    }
+}
+
+impl Check for Adler32 {
+    fn of(data: &[u8]) -> Adler32 {
+        let mut hash = Adler32::new();
+        hash.feed(data);
+        hash
+    }
+
+    fn append(&mut self, next: Adler32, len: usize) {
+        *self = Adler32::combine(self, &next, len);
+    }
+}
+
+/// A ZLIB encoder which compresses on several threads at once, see
+/// `flate::ParallelEncoder`. The checksum is computed by the threads along
+/// with the compression, a chunk at a time, and combined.
+pub struct ParallelEncoder<W> {
+    inner: Parallel<W, Adler32>,
+    header: Option<[u8; 2]>,
+}
+
+impl<W: Write> ParallelEncoder<W> {
+    /// Creates a new encoder with the given compression level, using as many
+    /// threads as there are cores
+    pub fn new(w: W, level: u8) -> ParallelEncoder<W> {
+        ParallelEncoder::with_threads(w, level, parallel::default_threads(), CHUNK_SIZE)
+    }
+
+    /// Creates a new encoder compressing chunks of `chunk_size` bytes of
+    /// input on up to `threads` threads at once
+    pub fn with_threads(w: W, level: u8, threads: usize, chunk_size: usize) -> ParallelEncoder<W> {
+        ParallelEncoder {
+            inner: Parallel::new(w, level, threads, chunk_size),
+            header: Some(header(flevel(level), false)),
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if let Some(header) = self.header.take() {
+            self.inner.w.write_all(&header)?;
+        }
+        Ok(())
+    }
+
+    /// This function is used to flag that this session of compression is done
+    /// with. The stream is finished up (final bytes are written), and then the
+    /// wrapped writer is returned.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let result = self
+            .write_header()
+            .and_then(|_| self.inner.finish())
+            .and_then(|_| {
+                let cksum = self.inner.check.result();
+                self.inner.w.write_u32::<BigEndian>(cksum)
+            });
+        (self.inner.w, result)
+    }
+}
+
+impl<W: Write> Write for ParallelEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_header()?;
+        self.inner.write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.inner.flush()
+    }
+}
+
+/// Joins complete ZLIB streams into a single stream, see `flate::Joiner`.
+/// The checksum of the joined stream is put together from those of the
+/// streams. The header tells the level of the first stream and a 32K window,
+/// as the other streams may use a larger window than the first.
+pub struct Joiner<W> {
+    inner: flate::Joiner<W>,
+    hash: Option<Adler32>,
+}
+
+impl<W: Write> Joiner<W> {
+    /// Creates a new joiner which writes the joined stream to `w`
+    pub fn new(w: W) -> Joiner<W> {
+        Joiner {
+            inner: flate::Joiner::new(w),
+            hash: None,
+        }
+    }
+
+    /// Appends the stream read from `r`, which is left right behind the
+    /// stream's checksum. Returns the length of the stream's data.
+    pub fn append<R: BufRead>(&mut self, mut r: R) -> io::Result<u64> {
+        let mut header = [0; 2];
+        r.read_exact(&mut header)?;
+        if header[1] & 0x20 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't join zlib streams with a preset dictionary",
+            ));
+        }
+        let level = Stream::new().validate_header(&mut &header[..])?.level;
+        if self.hash.is_none() {
+            self.inner
+                .get_mut()
+                .write_all(&self::header(level.flevel(), false))?;
+        }
+        let len = self.inner.append(&mut r)?;
+        let hash = Adler32::from_checksum(r.read_u32::<BigEndian>()?);
+        self.hash = Some(match self.hash {
+            Some(ref prev) => Adler32::combine(prev, &hash, len as usize),
+            None => hash,
+        });
+        Ok(len)
+    }
+
+    /// Finishes the joined stream, returning the wrapped writer. Without any
+    /// streams appended, this writes an empty stream.
+    pub fn finish(mut self) -> (W, io::Result<()>) {
+        let hash = match self.hash.take() {
+            Some(hash) => hash,
+            None => {
+                if let Err(e) = self.inner.get_mut().write_all(&header(flevel(6), false)) {
+                    return (self.inner.finish().0, Err(e));
+                }
+                Adler32::new()
+            }
+        };
+        let (mut w, result) = self.inner.finish();
+        let result = result.and_then(|_| w.write_u32::<BigEndian>(hash.result()));
+        (w, result)
+    }
 }
 
 #[cfg(test)]
 #[allow(warnings)]
 mod test {
-    use super::Decoder;
+    use super::{BufDecoder, Decoder, Encoder, Header, Joiner, Level, ParallelEncoder};
+    use crate::flate;
+    use crate::Adler32;
     use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
     use rand::random;
     use std::io::{BufReader, BufWriter, Read, Write};
@@ -202,22 +650,248 @@ mod test {
         assert!(&out[..] == &include_bytes!("data/test.txt")[..]);
     }
 
-    //fn roundtrip(bytes: &[u8]) {
-    //    let mut e = Encoder::new(MemWriter::new());
-    //    e.write(bytes);
-    //    let encoded = e.finish().unwrap();
-    //
-    //    let mut d = Decoder::new(BufReader::new(encoded));
-    //    let decoded = d.read_to_end();
-    //    assert_eq!(&decoded[..], bytes);
-    //}
-    //
-    //#[test]
-    //fn some_roundtrips() {
-    //    roundtrip(bytes!("test"));
-    //    roundtrip(bytes!(""));
-    //    roundtrip(include_bytes!("data/test.txt"));
-    //}
+    #[test]
+    fn exact_consumption() {
+        let mut data = Vec::new();
+        data.extend(&include_bytes!("data/test.z.1")[..]);
+        data.extend(&include_bytes!("data/test.z.9")[..]);
+        data.extend(b"trailer");
+        for &cap in [1, 5, 8192].iter() {
+            let mut r = BufReader::with_capacity(cap, &data[..]);
+            for _ in 0..2 {
+                let mut d = BufDecoder::new(&mut r);
+                let mut buf = Vec::new();
+                d.read_to_end(&mut buf).unwrap();
+                assert!(&buf[..] == &include_bytes!("data/test.txt")[..]);
+            }
+            let mut rest = Vec::new();
+            r.read_to_end(&mut rest).unwrap();
+            assert_eq!(&rest[..], b"trailer");
+        }
+    }
+
+    #[test]
+    fn trailing() {
+        let mut data = include_bytes!("data/test.z.5").to_vec();
+        data.extend(b"trailer");
+        let mut d = Decoder::new(&data[..]);
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(&buf[..] == &include_bytes!("data/test.txt")[..]);
+        assert_eq!(d.trailing(), b"trailer");
+    }
+
+    // Wraps DEFLATE data compressed against `dict` into a zlib stream
+    fn with_dictionary(input: &[u8], dict: &[u8]) -> Vec<u8> {
+        let mut e = flate::Encoder::with_dictionary(Vec::new(), 6, dict);
+        e.write_all(input).unwrap();
+        let (deflated, err) = e.finish();
+        err.unwrap();
+        let mut hash = Adler32::new();
+        hash.feed(dict);
+        // 32K window, default level, FDICT set
+        let mut data = vec![0x78, 0xbb];
+        data.write_u32::<BigEndian>(hash.result()).unwrap();
+        data.extend(deflated);
+        let mut hash = Adler32::new();
+        hash.feed(input);
+        data.write_u32::<BigEndian>(hash.result()).unwrap();
+        data
+    }
+
+    #[test]
+    fn dictionary() {
+        let dict = b"the quick brown fox jumps over the lazy dog";
+        let msg = b"the lazy fox jumps over the quick dog";
+        let data = with_dictionary(msg, dict);
+
+        let mut buf = Vec::new();
+        Decoder::with_dictionary(&data[..], dict)
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], &msg[..]);
+        let mut buf = Vec::new();
+        BufDecoder::with_dictionary(&data[..], dict)
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..], &msg[..]);
+
+        // the dictionary is required and has to be the right one
+        let mut buf = Vec::new();
+        assert!(Decoder::new(&data[..]).read_to_end(&mut buf).is_err());
+        let mut buf = Vec::new();
+        assert!(Decoder::with_dictionary(&data[..], b"the quick brown fox")
+            .read_to_end(&mut buf)
+            .is_err());
+
+        // streams without one decode regardless
+        let mut buf = Vec::new();
+        Decoder::with_dictionary(&include_bytes!("data/test.z.1")[..], dict)
+            .read_to_end(&mut buf)
+            .unwrap();
+        assert!(&buf[..] == &include_bytes!("data/test.txt")[..]);
+    }
+
+    // A zlib stream declaring the given window size, with the data
+    // compressed to fit `used`
+    fn with_window(input: &[u8], declared: usize, used: usize) -> Vec<u8> {
+        let mut e = flate::Encoder::with_level(Vec::new(), 6);
+        e.limit_window(used);
+        e.write_all(input).unwrap();
+        let (deflated, err) = e.finish();
+        err.unwrap();
+        let cmf = ((declared.trailing_zeros() as u8 - 8) << 4) | 8;
+        let flg = 31 - ((cmf as u16) << 8) % 31;
+        let mut data = vec![cmf, flg as u8];
+        data.extend(deflated);
+        let mut hash = Adler32::new();
+        hash.feed(input);
+        data.write_u32::<BigEndian>(hash.result()).unwrap();
+        data
+    }
+
+    #[test]
+    fn window_sizes() {
+        let input = &include_bytes!("data/test.large")[..100000];
+        for bits in 8..16 {
+            let size = 1 << bits;
+            let data = with_window(input, size, size);
+            let mut d = Decoder::new(&data[..]);
+            assert_eq!(d.header().unwrap().window_size, size);
+            let mut buf = Vec::new();
+            d.read_to_end(&mut buf).unwrap();
+            assert!(&buf[..] == input);
+        }
+
+        // the declared window is enforced, and has to be valid
+        let data = with_window(input, 256, 32768);
+        let mut buf = Vec::new();
+        assert!(Decoder::new(&data[..]).read_to_end(&mut buf).is_err());
+        let mut data = with_window(input, 256, 256);
+        data[0] = 0x88;
+        data[1] = 31 - ((0x88u16 << 8) % 31) as u8;
+        assert!(BufDecoder::new(&data[..]).header().is_err());
+    }
+
+    #[test]
+    fn header() {
+        let mut d = Decoder::new(&include_bytes!("data/test.z.9")[..]);
+        assert_eq!(
+            *d.header().unwrap(),
+            Header {
+                window_size: 32768,
+                level: Level::Maximum,
+                dictionary: None,
+            }
+        );
+        let mut buf = Vec::new();
+        d.read_to_end(&mut buf).unwrap();
+        assert!(&buf[..] == &include_bytes!("data/test.txt")[..]);
+        let level = Decoder::new(&include_bytes!("data/test.z.1")[..])
+            .header()
+            .unwrap()
+            .level;
+        assert_eq!(level, Level::Fastest);
+
+        let dict = b"the quick brown fox jumps over the lazy dog";
+        let data = with_dictionary(b"the lazy fox", dict);
+        let mut hash = Adler32::new();
+        hash.feed(dict);
+        let mut d = BufDecoder::with_dictionary(&data[..], dict);
+        assert_eq!(d.header().unwrap().dictionary, Some(hash.result()));
+        assert_eq!(d.header().unwrap().level, Level::Default);
+    }
+
+    #[test]
+    fn parallel() {
+        let input = &include_bytes!("data/test.large")[..200000];
+        for &level in [1, 6, 9].iter() {
+            let mut e = ParallelEncoder::with_threads(Vec::new(), level, 3, 30000);
+            e.write_all(input).unwrap();
+            let (encoded, result) = e.finish();
+            result.unwrap();
+            let mut buf = Vec::new();
+            Decoder::new(&encoded[..]).read_to_end(&mut buf).unwrap();
+            assert!(&buf[..] == input);
+        }
+        // headers as zlib writes them
+        assert_eq!(super::header(super::flevel(1), false), [0x78, 0x01]);
+        assert_eq!(super::header(super::flevel(6), false), [0x78, 0x9c]);
+        assert_eq!(super::header(super::flevel(9), false), [0x78, 0xda]);
+        assert_eq!(super::header(super::flevel(1), true), [0x78, 0x20]);
+    }
+
+    #[test]
+    fn join() {
+        let text = &include_bytes!("data/test.txt")[..];
+        let pieces: Vec<&[u8]> = vec![text, b"", &text[..1000], b"abc"];
+        let mut j = Joiner::new(Vec::new());
+        for piece in pieces.iter() {
+            let mut e = ParallelEncoder::with_threads(Vec::new(), 6, 1, 1000);
+            e.write_all(piece).unwrap();
+            let (encoded, result) = e.finish();
+            result.unwrap();
+            j.append(&encoded[..]).unwrap();
+        }
+        let (joined, result) = j.finish();
+        result.unwrap();
+        let mut buf = Vec::new();
+        Decoder::new(&joined[..]).read_to_end(&mut buf).unwrap();
+        assert!(buf == pieces.concat());
+
+        let (empty, _) = Joiner::new(Vec::new()).finish();
+        assert!(Decoder::new(&empty[..]).read_to_end(&mut buf).is_ok());
+    }
+
+    fn roundtrip(bytes: &[u8]) {
+        for level in 0..10 {
+            let mut e = Encoder::with_level(Vec::new(), level);
+            e.write_all(bytes).unwrap();
+            let (encoded, result) = e.finish();
+            result.unwrap();
+
+            let mut d = Decoder::new(BufReader::new(&encoded[..]));
+            let mut decoded = Vec::new();
+            d.read_to_end(&mut decoded).unwrap();
+            assert_eq!(&decoded[..], bytes);
+        }
+    }
+
+    #[test]
+    fn some_roundtrips() {
+        roundtrip(b"test");
+        roundtrip(b"");
+        roundtrip(include_bytes!("data/test.txt"));
+    }
+
+    #[test]
+    fn encoded_format() {
+        // the same header and trailer as zlib's
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(include_bytes!("data/test.txt")).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        let reference = include_bytes!("data/test.z.6");
+        assert_eq!(encoded[..2], reference[..2]);
+        assert_eq!(
+            encoded[encoded.len() - 4..],
+            reference[reference.len() - 4..]
+        );
+
+        let mut e = Encoder::with_level(Vec::new(), 9);
+        e.flush().unwrap();
+        let (encoded, _) = e.finish();
+        assert_eq!(encoded[..2], [0x78, 0xda]);
+
+        // with a dictionary, FDICT is set and the ID follows
+        let dict = b"the quick brown fox jumps over the lazy dog";
+        let msg = b"the lazy fox jumps over the quick dog";
+        let mut e = Encoder::with_dictionary(Vec::new(), 6, dict);
+        e.write_all(msg).unwrap();
+        let (encoded, result) = e.finish();
+        result.unwrap();
+        assert!(encoded == with_dictionary(msg, dict));
+    }
 
     #[cfg(feature = "unstable")]
     #[bench]
@@ -227,7 +901,7 @@ mod test {
         let mut output = [0u8; 65536];
         let mut output_size = 0;
         bh.iter(|| {
-            d.inner.r = BufReader::new(input);
+            d.r = BufReader::new(input);
             d.reset();
             output_size = d.read(&mut output[..]).unwrap();
         });